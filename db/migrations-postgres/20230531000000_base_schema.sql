@@ -0,0 +1,34 @@
+-- the postgres counterpart of db/migrations, one file per sqlite migration with the
+-- same version. dates stay text in the same 'YYYY-MM-DD HH:MM:SS' form so queries
+-- compare them the same way on both. foreign keys are deferrable so rrs-admin can
+-- import tables in any order
+CREATE TABLE IF NOT EXISTS "user" (
+    id BIGSERIAL PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    password TEXT NOT NULL,
+    created TEXT NOT NULL,
+    profile_pic TEXT NOT NULL DEFAULT '',
+    admin BOOLEAN NOT NULL DEFAULT FALSE,
+    premium BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS project (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    proj_start_date TEXT NOT NULL,
+    proj_end_date TEXT NOT NULL DEFAULT '',
+    owner BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    -- comma separated user ids
+    participants TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS proj_tasks (
+    id BIGSERIAL PRIMARY KEY,
+    description TEXT NOT NULL,
+    task_start_date TEXT NOT NULL,
+    task_end_date TEXT NOT NULL DEFAULT '',
+    owner_proj BIGINT NOT NULL REFERENCES project(id) ON DELETE CASCADE DEFERRABLE,
+    -- seconds between start and end, set on completion
+    time_delta BIGINT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,11 @@
+-- workflow state and per-column ordering for the project board
+ALTER TABLE proj_tasks ADD COLUMN status TEXT NOT NULL DEFAULT 'todo';
+ALTER TABLE proj_tasks ADD COLUMN position BIGINT NOT NULL DEFAULT 0;
+
+-- optional work-in-progress limit per project board column
+CREATE TABLE IF NOT EXISTS board_column (
+    owner_proj BIGINT NOT NULL REFERENCES project(id) ON DELETE CASCADE DEFERRABLE,
+    status TEXT NOT NULL,
+    wip_limit BIGINT,
+    PRIMARY KEY (owner_proj, status)
+);
@@ -0,0 +1,6 @@
+-- priority runs from 0 (P0, most urgent) to 3 (P3)
+ALTER TABLE proj_tasks ADD COLUMN priority BIGINT NOT NULL DEFAULT 2;
+-- stored as "%Y-%m-%d %H:%M:%S" so it compares against db::NOW
+ALTER TABLE proj_tasks ADD COLUMN due_date TEXT;
+
+CREATE INDEX IF NOT EXISTS proj_tasks_due_date ON proj_tasks (due_date);
@@ -0,0 +1,13 @@
+ALTER TABLE proj_tasks ADD COLUMN parent_id BIGINT REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE;
+
+CREATE INDEX IF NOT EXISTS proj_tasks_parent_id ON proj_tasks (parent_id);
+
+CREATE TABLE IF NOT EXISTS checklist_item (
+    id BIGSERIAL PRIMARY KEY,
+    task_id BIGINT NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE,
+    text TEXT NOT NULL,
+    done BOOLEAN NOT NULL DEFAULT FALSE,
+    position BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS checklist_item_task_id ON checklist_item (task_id);
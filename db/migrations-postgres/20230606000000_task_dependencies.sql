@@ -0,0 +1,12 @@
+-- estimated duration of a task in seconds, used for the critical path
+ALTER TABLE proj_tasks ADD COLUMN estimate BIGINT;
+
+-- task_id cannot be completed before blocked_by is
+CREATE TABLE IF NOT EXISTS task_dependency (
+    task_id BIGINT NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE,
+    blocked_by BIGINT NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE,
+    PRIMARY KEY (task_id, blocked_by),
+    CHECK (task_id <> blocked_by)
+);
+
+CREATE INDEX IF NOT EXISTS task_dependency_blocked_by ON task_dependency (blocked_by);
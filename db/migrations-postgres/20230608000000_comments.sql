@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS comment (
+    id BIGSERIAL PRIMARY KEY,
+    author BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    project_id BIGINT NOT NULL REFERENCES project(id) ON DELETE CASCADE DEFERRABLE,
+    -- NULL for comments on the project itself
+    task_id BIGINT REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE,
+    -- the comment this one replies to
+    parent_id BIGINT REFERENCES comment(id) ON DELETE CASCADE DEFERRABLE,
+    body TEXT NOT NULL,
+    created TEXT NOT NULL,
+    edited TEXT NOT NULL DEFAULT '',
+    deleted BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS comment_project_id ON comment (project_id);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS notification (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    -- what happened, e.g. 'mention'
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    -- where the notification leads when clicked
+    link TEXT NOT NULL DEFAULT '',
+    created TEXT NOT NULL,
+    read BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS notification_user_id ON notification (user_id, read);
@@ -0,0 +1,6 @@
+-- users who get notified when a task is completed
+CREATE TABLE IF NOT EXISTS task_watch (
+    task_id BIGINT NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE DEFERRABLE,
+    user_id BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    PRIMARY KEY (task_id, user_id)
+);
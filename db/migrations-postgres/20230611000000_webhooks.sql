@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS webhook (
+    id BIGSERIAL PRIMARY KEY,
+    owner BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    -- NULL subscribes to every project of the owner
+    project_id BIGINT REFERENCES project(id) ON DELETE SET NULL DEFERRABLE,
+    -- set when the project is deleted, the webhook is kept for its delivery history
+    -- and this stops its NULL project_id from subscribing to every project
+    project_deleted BOOLEAN NOT NULL DEFAULT FALSE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- comma separated event names, e.g. 'task.created,task.completed'
+    events TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhook(id) ON DELETE CASCADE DEFERRABLE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt TEXT NOT NULL,
+    response_code BIGINT,
+    last_error TEXT NOT NULL DEFAULT '',
+    created TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_pending ON webhook_delivery (status, next_attempt);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS notification_preference (
+    user_id BIGINT PRIMARY KEY REFERENCES "user"(id) ON DELETE CASCADE DEFERRABLE,
+    email_notifications BOOLEAN NOT NULL DEFAULT FALSE,
+    email_digest BOOLEAN NOT NULL DEFAULT TRUE,
+    -- only notifications newer than this are emailed
+    updated TEXT NOT NULL
+);
+
+ALTER TABLE notification ADD COLUMN emailed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS job (
+    id BIGSERIAL PRIMARY KEY,
+    -- set for recurring jobs, which are seeded by name on every start
+    name TEXT NOT NULL DEFAULT '',
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL DEFAULT '',
+    -- cron expression, '' for one-off jobs
+    schedule TEXT NOT NULL DEFAULT '',
+    -- 'YYYY-MM-DD HH:MM:SS' UTC, comparable with db::NOW
+    run_at TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'scheduled',
+    attempts BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT NOT NULL DEFAULT '',
+    locked_at TEXT,
+    created TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS job_name ON job (name) WHERE name <> '';
+CREATE INDEX IF NOT EXISTS job_due ON job (status, run_at);
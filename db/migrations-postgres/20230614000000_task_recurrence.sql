@@ -0,0 +1,22 @@
+-- a recurring task; every instance is a regular task pointing back here
+CREATE TABLE IF NOT EXISTS task_recurrence (
+    id BIGSERIAL PRIMARY KEY,
+    project_id BIGINT NOT NULL REFERENCES project(id) ON DELETE CASCADE DEFERRABLE,
+    -- RFC 5545 RRULE, e.g. FREQ=WEEKLY;BYDAY=MO,TH
+    rrule TEXT NOT NULL,
+    -- first occurrence, 'YYYY-MM-DD HH:MM:SS' UTC like due dates
+    dtstart TEXT NOT NULL,
+    -- copied to new instances when the current one is gone
+    description TEXT NOT NULL,
+    priority BIGINT NOT NULL DEFAULT 2,
+    -- due date of the newest instance
+    last_occurrence TEXT NOT NULL,
+    -- which occurrence of the series last_occurrence is, counting dtstart as 1
+    occurrences BIGINT NOT NULL DEFAULT 1,
+    current_task BIGINT REFERENCES proj_tasks(id) ON DELETE SET NULL DEFERRABLE,
+    -- 'active', 'paused' or 'ended'
+    status TEXT NOT NULL DEFAULT 'active',
+    created TEXT NOT NULL
+);
+
+ALTER TABLE proj_tasks ADD COLUMN recurrence_id BIGINT REFERENCES task_recurrence(id) ON DELETE SET NULL DEFERRABLE;
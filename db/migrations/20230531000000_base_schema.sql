@@ -0,0 +1,32 @@
+-- the tables the app started out with, so a fresh database can be migrated from
+-- scratch. existing databases already have them and are left alone
+CREATE TABLE IF NOT EXISTS user (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    password TEXT NOT NULL,
+    created TEXT NOT NULL,
+    profile_pic TEXT NOT NULL DEFAULT '',
+    admin BOOLEAN NOT NULL DEFAULT 0,
+    premium BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS project (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    proj_start_date TEXT NOT NULL,
+    proj_end_date TEXT NOT NULL DEFAULT '',
+    owner INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    -- comma separated user ids
+    participants TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS proj_tasks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    description TEXT NOT NULL,
+    task_start_date TEXT NOT NULL,
+    task_end_date TEXT NOT NULL DEFAULT '',
+    owner_proj INTEGER NOT NULL REFERENCES project(id) ON DELETE CASCADE,
+    -- seconds between start and end, set on completion
+    time_delta INTEGER NOT NULL DEFAULT 0
+);
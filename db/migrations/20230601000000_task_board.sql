@@ -0,0 +1,19 @@
+-- workflow state and per-column ordering for the project board
+ALTER TABLE proj_tasks ADD COLUMN status TEXT NOT NULL DEFAULT 'todo';
+ALTER TABLE proj_tasks ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE proj_tasks SET status = 'done' WHERE task_end_date IS NOT NULL AND task_end_date <> '';
+UPDATE proj_tasks SET position = (
+    SELECT COUNT(*) FROM proj_tasks t
+    WHERE t.owner_proj = proj_tasks.owner_proj
+    AND t.status = proj_tasks.status
+    AND t.id < proj_tasks.id
+);
+
+-- optional work-in-progress limit per project board column
+CREATE TABLE IF NOT EXISTS board_column (
+    owner_proj INTEGER NOT NULL REFERENCES project(id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    wip_limit INTEGER,
+    PRIMARY KEY (owner_proj, status)
+);
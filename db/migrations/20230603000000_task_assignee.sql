@@ -0,0 +1 @@
+ALTER TABLE proj_tasks ADD COLUMN assignee INTEGER REFERENCES user(id) ON DELETE SET NULL;
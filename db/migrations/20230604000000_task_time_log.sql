@@ -0,0 +1,12 @@
+-- previous start/end dates of a task, kept when it is reopened or its times are corrected
+CREATE TABLE IF NOT EXISTS task_time_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    task_start_date TEXT NOT NULL,
+    task_end_date TEXT NOT NULL,
+    time_delta INTEGER NOT NULL DEFAULT 0,
+    reason TEXT NOT NULL,
+    recorded TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS task_time_log_task_id ON task_time_log (task_id);
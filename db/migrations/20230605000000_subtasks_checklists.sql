@@ -0,0 +1,13 @@
+ALTER TABLE proj_tasks ADD COLUMN parent_id INTEGER REFERENCES proj_tasks(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS proj_tasks_parent_id ON proj_tasks (parent_id);
+
+CREATE TABLE IF NOT EXISTS checklist_item (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    text TEXT NOT NULL,
+    done BOOLEAN NOT NULL DEFAULT FALSE,
+    position INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS checklist_item_task_id ON checklist_item (task_id);
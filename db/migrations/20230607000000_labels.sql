@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS label (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    owner INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    color TEXT NOT NULL DEFAULT '#888888',
+    UNIQUE (owner, name)
+);
+
+CREATE TABLE IF NOT EXISTS project_label (
+    project_id INTEGER NOT NULL REFERENCES project(id) ON DELETE CASCADE,
+    label_id INTEGER NOT NULL REFERENCES label(id) ON DELETE CASCADE,
+    PRIMARY KEY (project_id, label_id)
+);
+
+CREATE TABLE IF NOT EXISTS task_label (
+    task_id INTEGER NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    label_id INTEGER NOT NULL REFERENCES label(id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, label_id)
+);
+
+CREATE INDEX IF NOT EXISTS project_label_label_id ON project_label (label_id);
+CREATE INDEX IF NOT EXISTS task_label_label_id ON task_label (label_id);
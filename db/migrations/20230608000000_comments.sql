@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS comment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    author INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    project_id INTEGER NOT NULL REFERENCES project(id) ON DELETE CASCADE,
+    -- NULL for comments on the project itself
+    task_id INTEGER REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    -- the comment this one replies to
+    parent_id INTEGER REFERENCES comment(id) ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    created TEXT NOT NULL,
+    edited TEXT NOT NULL DEFAULT '',
+    deleted BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS comment_project_id ON comment (project_id);
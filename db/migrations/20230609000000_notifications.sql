@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS notification (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    -- what happened, e.g. 'mention'
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    -- where the notification leads when clicked
+    link TEXT NOT NULL DEFAULT '',
+    created TEXT NOT NULL,
+    read BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS notification_user_id ON notification (user_id, read);
@@ -0,0 +1,6 @@
+-- users who get notified when a task is completed
+CREATE TABLE IF NOT EXISTS task_watch (
+    task_id INTEGER NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, user_id)
+);
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS webhook (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    owner INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    -- NULL subscribes to every project of the owner
+    project_id INTEGER REFERENCES project(id) ON DELETE SET NULL,
+    -- set when the project is deleted, the webhook is kept for its delivery history
+    -- and this stops its NULL project_id from subscribing to every project
+    project_deleted BOOLEAN NOT NULL DEFAULT 0,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- comma separated event names, e.g. 'task.created,task.completed'
+    events TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT 1,
+    created TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL REFERENCES webhook(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt TEXT NOT NULL,
+    response_code INTEGER,
+    last_error TEXT NOT NULL DEFAULT '',
+    created TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_pending ON webhook_delivery (status, next_attempt);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS notification_preference (
+    user_id INTEGER PRIMARY KEY REFERENCES user(id) ON DELETE CASCADE,
+    email_notifications BOOLEAN NOT NULL DEFAULT 0,
+    email_digest BOOLEAN NOT NULL DEFAULT 1,
+    -- only notifications newer than this are emailed
+    updated TEXT NOT NULL
+);
+
+ALTER TABLE notification ADD COLUMN emailed BOOLEAN NOT NULL DEFAULT 0;
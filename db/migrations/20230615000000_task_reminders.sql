@@ -0,0 +1,21 @@
+-- fires once, at remind_at or offset_minutes before the task's due date
+CREATE TABLE IF NOT EXISTS task_reminder (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL REFERENCES proj_tasks(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES user(id) ON DELETE CASCADE,
+    -- 'YYYY-MM-DD HH:MM:SS' UTC, comparable with datetime('now')
+    remind_at TEXT,
+    offset_minutes INTEGER,
+    -- 'pending', 'sent', 'failed' or 'cancelled'
+    status TEXT NOT NULL DEFAULT 'pending',
+    -- failed deliveries so far, the next one is tried at retry_at
+    attempts INTEGER NOT NULL DEFAULT 0,
+    retry_at TEXT,
+    created TEXT NOT NULL,
+    CHECK ((remind_at IS NULL) <> (offset_minutes IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS task_reminder_pending ON task_reminder (status, task_id);
+
+-- 'app', 'email' or 'webhook'
+ALTER TABLE notification_preference ADD COLUMN reminder_channel TEXT NOT NULL DEFAULT 'app';
@@ -0,0 +1,20 @@
+-- dates written from rust used to look like '2023-06-01 09:00:00.123456789 UTC'. they
+-- become 'YYYY-MM-DD HH:MM:SS' like everything else, so they compare with db::NOW
+UPDATE "user" SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE project SET proj_start_date = substr(proj_start_date, 1, 19) WHERE proj_start_date LIKE '% UTC';
+UPDATE project SET proj_end_date = substr(proj_end_date, 1, 19) WHERE proj_end_date LIKE '% UTC';
+UPDATE proj_tasks SET task_start_date = substr(task_start_date, 1, 19) WHERE task_start_date LIKE '% UTC';
+UPDATE proj_tasks SET task_end_date = substr(task_end_date, 1, 19) WHERE task_end_date LIKE '% UTC';
+UPDATE task_time_log SET task_start_date = substr(task_start_date, 1, 19) WHERE task_start_date LIKE '% UTC';
+UPDATE task_time_log SET task_end_date = substr(task_end_date, 1, 19) WHERE task_end_date LIKE '% UTC';
+UPDATE task_time_log SET recorded = substr(recorded, 1, 19) WHERE recorded LIKE '% UTC';
+UPDATE comment SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE comment SET edited = substr(edited, 1, 19) WHERE edited LIKE '% UTC';
+UPDATE notification SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE webhook SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE webhook_delivery SET next_attempt = substr(next_attempt, 1, 19) WHERE next_attempt LIKE '% UTC';
+UPDATE webhook_delivery SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE notification_preference SET updated = substr(updated, 1, 19) WHERE updated LIKE '% UTC';
+UPDATE job SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE task_recurrence SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
+UPDATE task_reminder SET created = substr(created, 1, 19) WHERE created LIKE '% UTC';
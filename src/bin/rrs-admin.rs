@@ -0,0 +1,531 @@
+// Maintenance tasks that shouldn't need a running server or hand-edited SQLite,
+// like creating the first admin. It reads the database url from the same place
+// the server does: `.env` through dotenvy, then Rocket.toml and ROCKET_ variables.
+//
+// Users are created and passwords hashed by the server's own user module, the
+// rest is bookkeeping the server never does.
+use rocket::serde::json::{serde_json, Value};
+use rocket_db_pools::sqlx::{self, Column, Row, TypeInfo, ValueRef};
+use rust_rocket_sqlx::auth::hash_password;
+use rust_rocket_sqlx::db::{self, DbPool, DbRow, MIGRATOR};
+use rust_rocket_sqlx::error::AppError;
+use rust_rocket_sqlx::{seed, user};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::process::ExitCode;
+
+// every table but the backend's own and the migration bookkeeping, in creation order
+#[cfg(not(feature = "postgres"))]
+const DATA_TABLES: &str = "SELECT name FROM sqlite_master
+    WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '_sqlx_%'
+    ORDER BY rowid";
+#[cfg(feature = "postgres")]
+const DATA_TABLES: &str = "SELECT table_name AS name FROM information_schema.tables
+    WHERE table_schema = current_schema() AND table_type = 'BASE TABLE'
+    AND table_name NOT LIKE '\\_sqlx\\_%'
+    ORDER BY CAST(CAST(quote_ident(table_name) AS regclass) AS oid)";
+
+// the declared type of every column of a table
+#[cfg(not(feature = "postgres"))]
+const COLUMN_TYPES: &str = "SELECT name, type FROM pragma_table_info(?)";
+#[cfg(feature = "postgres")]
+const COLUMN_TYPES: &str = "SELECT column_name AS name, data_type AS type
+    FROM information_schema.columns
+    WHERE table_schema = current_schema() AND table_name = ?";
+
+const USAGE: &str = "usage: rrs-admin <command>
+
+commands:
+    create-user <name> <email> [--admin] [--premium]
+    set-admin <email> <on|off>
+    set-premium <email> <on|off>
+    reset-password <email>
+    list-users
+    migrate
+    export <file>
+    import <file>
+    vacuum
+    seed [--seed <n>] [--users <n>] [--projects <n>] [--participants <n>] [--tasks <n>]
+
+passwords are read from stdin, so they stay out of the shell history";
+
+type AdminResult = Result<(), String>;
+
+fn database_url() -> Result<String, String> {
+    dotenvy::dotenv().ok();
+    rocket::Config::figment()
+        .extract_inner("databases.dev-db.url")
+        .map_err(|e| format!("Failed to read the database url: {}", e))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn connect() -> Result<DbPool, String> {
+    use sqlx::sqlite::SqliteConnectOptions;
+    use std::str::FromStr;
+
+    let url = database_url()?;
+    let options = SqliteConnectOptions::from_str(&url)
+        .map_err(|e| e.to_string())?
+        .foreign_keys(true);
+    DbPool::connect_with(options)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", url, e))
+}
+
+#[cfg(feature = "postgres")]
+async fn connect() -> Result<DbPool, String> {
+    let url = database_url()?;
+    DbPool::connect(&url)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", url, e))
+}
+
+fn read_password() -> Result<String, String> {
+    eprint!("password: ");
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| e.to_string())?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    match password.is_empty() {
+        true => Err("The password can't be empty".to_string()),
+        false => Ok(password.to_string()),
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("Expected on or off, got {}", value)),
+    }
+}
+
+async fn create_user(pool: &DbPool, args: &[String]) -> AdminResult {
+    let (name, email) = match args {
+        [name, email, ..] => (name, email),
+        _ => return Err(USAGE.to_string()),
+    };
+    let flags = &args[2..];
+    let admin = flags.iter().any(|flag| flag == "--admin");
+    let premium = flags.iter().any(|flag| flag == "--premium");
+    let password = read_password()?;
+
+    let fail = |e: AppError| match e {
+        AppError::Database(e) => format!("Failed to create user: {}", e),
+        e => format!("Failed to create user: {}", e),
+    };
+    let mut tx = pool.begin().await.map_err(|e| fail(e.into()))?;
+    let id = user::add_user(&mut tx, name, email, &password)
+        .await
+        .map_err(fail)?;
+    db::query("UPDATE \"user\" SET admin = ?, premium = ? WHERE id = ?")
+        .bind(admin)
+        .bind(premium)
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| fail(e.into()))?;
+    tx.commit().await.map_err(|e| fail(e.into()))?;
+
+    println!("created user {} <{}>", id, email);
+    Ok(())
+}
+
+async fn set_flag(pool: &DbPool, flag: &str, args: &[String]) -> AdminResult {
+    let (email, value) = match args {
+        [email, value] => (email, parse_switch(value)?),
+        _ => return Err(USAGE.to_string()),
+    };
+    // `flag` is one of two literals, never user input
+    let result = db::query(&format!("UPDATE \"user\" SET {} = ? WHERE email = ?", flag))
+        .bind(value)
+        .bind(email)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update user: {}", e))?;
+
+    match result.rows_affected() {
+        0 => Err(format!("No user with email {}", email)),
+        _ => {
+            println!(
+                "{} is {} for {}",
+                flag,
+                if value { "on" } else { "off" },
+                email
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn reset_password(pool: &DbPool, args: &[String]) -> AdminResult {
+    let email = match args {
+        [email] => email,
+        _ => return Err(USAGE.to_string()),
+    };
+    let password = hash_password(&read_password()?);
+    let result = db::query("UPDATE \"user\" SET password = ? WHERE email = ?")
+        .bind(password)
+        .bind(email)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to reset password: {}", e))?;
+
+    match result.rows_affected() {
+        0 => Err(format!("No user with email {}", email)),
+        _ => {
+            println!("password reset for {}", email);
+            Ok(())
+        }
+    }
+}
+
+async fn list_users(pool: &DbPool) -> AdminResult {
+    let rows =
+        db::query("SELECT id, name, email, admin, premium, created FROM \"user\" ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| format!("Failed to list users: {}", e))?;
+
+    println!(
+        "{:>4}  {:<24} {:<32} {:<6} {:<8} created",
+        "id", "name", "email", "admin", "premium"
+    );
+    for row in rows {
+        println!(
+            "{:>4}  {:<24} {:<32} {:<6} {:<8} {}",
+            row.get::<i64, _>("id"),
+            row.get::<String, _>("name"),
+            row.get::<String, _>("email"),
+            row.get::<bool, _>("admin"),
+            row.get::<bool, _>("premium"),
+            row.get::<String, _>("created"),
+        );
+    }
+    Ok(())
+}
+
+async fn migrate(pool: &DbPool) -> AdminResult {
+    MIGRATOR
+        .run(pool)
+        .await
+        .map_err(|e| format!("Failed to run migrations: {}", e))?;
+    println!("database is up to date");
+    Ok(())
+}
+
+async fn data_tables(pool: &DbPool) -> Result<Vec<String>, sqlx::Error> {
+    let rows = db::query(DATA_TABLES).fetch_all(pool).await?;
+    Ok(rows.iter().map(|row| row.get("name")).collect())
+}
+
+// sqlite is dynamically typed, so each value is read by its own storage class;
+// postgres reports the column type instead
+fn row_to_json(row: &DbRow) -> Result<Value, sqlx::Error> {
+    let mut object = serde_json::Map::new();
+    for column in row.columns() {
+        let i = column.ordinal();
+        let raw = row.try_get_raw(i)?;
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" | "INT8" => Value::from(row.try_get::<i64, _>(i)?),
+                "REAL" | "FLOAT8" => Value::from(row.try_get::<f64, _>(i)?),
+                "BOOL" => Value::from(row.try_get::<bool, _>(i)?),
+                "BLOB" | "BYTEA" => Value::from(hex::encode(row.try_get::<Vec<u8>, _>(i)?)),
+                _ => Value::from(row.try_get::<String, _>(i)?),
+            }
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+async fn export(pool: &DbPool, args: &[String]) -> AdminResult {
+    let path = match args {
+        [path] => path,
+        _ => return Err(USAGE.to_string()),
+    };
+    let fail = |e: sqlx::Error| format!("Failed to export: {}", e);
+
+    let mut tables = vec![];
+    for table in data_tables(pool).await.map_err(fail)? {
+        let rows = db::query(&format!("SELECT * FROM \"{}\"", table))
+            .fetch_all(pool)
+            .await
+            .map_err(fail)?;
+        let rows = rows
+            .iter()
+            .map(row_to_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(fail)?;
+        println!("{}: {} rows", table, rows.len());
+        tables.push(serde_json::json!({"table": table, "rows": rows}));
+    }
+
+    let dump = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "exported": db::now(),
+        "tables": tables,
+    });
+    let file =
+        std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    serde_json::to_writer_pretty(file, &dump)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// how a value from a dump is bound, going by the declared type of its column. a
+// dump from one backend can be imported into the other, and sqlite exports
+// booleans as 0 and 1
+#[derive(Clone, Copy)]
+enum ColumnKind {
+    Integer,
+    Real,
+    Boolean,
+    Text,
+}
+
+impl ColumnKind {
+    fn of(declared: &str) -> Self {
+        let declared = declared.to_lowercase();
+        if declared.contains("bool") {
+            ColumnKind::Boolean
+        } else if declared.contains("int") {
+            ColumnKind::Integer
+        } else if ["real", "double", "float"]
+            .iter()
+            .any(|name| declared.contains(name))
+        {
+            ColumnKind::Real
+        } else {
+            ColumnKind::Text
+        }
+    }
+
+    fn bind<'q>(self, query: db::DbQuery<'q>, value: &Value) -> db::DbQuery<'q> {
+        match (value, self) {
+            (Value::Null, ColumnKind::Integer) => query.bind(None::<i64>),
+            (Value::Null, ColumnKind::Real) => query.bind(None::<f64>),
+            (Value::Null, ColumnKind::Boolean) => query.bind(None::<bool>),
+            (Value::Null, ColumnKind::Text) => query.bind(None::<String>),
+            (Value::Number(n), ColumnKind::Boolean) => query.bind(n.as_f64() != Some(0.0)),
+            (Value::Number(n), ColumnKind::Real) => query.bind(n.as_f64()),
+            (Value::Number(n), _) => match n.as_i64() {
+                Some(n) => query.bind(n),
+                None => query.bind(n.as_f64()),
+            },
+            (Value::Bool(b), _) => query.bind(*b),
+            (Value::String(s), _) => query.bind(s.clone()),
+            (other, _) => query.bind(other.to_string()),
+        }
+    }
+}
+
+async fn column_kinds(
+    pool: &DbPool,
+    table: &str,
+) -> Result<HashMap<String, ColumnKind>, sqlx::Error> {
+    let rows = db::query(COLUMN_TYPES).bind(table).fetch_all(pool).await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                row.get("name"),
+                ColumnKind::of(&row.get::<String, _>("type")),
+            )
+        })
+        .collect())
+}
+
+// rows arrive table by table, so references are checked at commit instead
+#[cfg(not(feature = "postgres"))]
+const DEFER_FOREIGN_KEYS: &str = "PRAGMA defer_foreign_keys = ON";
+#[cfg(feature = "postgres")]
+const DEFER_FOREIGN_KEYS: &str = "SET CONSTRAINTS ALL DEFERRED";
+
+// replaces the contents of every table in the dump, all or nothing
+async fn import(pool: &DbPool, args: &[String]) -> AdminResult {
+    let path = match args {
+        [path] => path,
+        _ => return Err(USAGE.to_string()),
+    };
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let dump: Value =
+        serde_json::from_reader(file).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let entries = dump["tables"]
+        .as_array()
+        .ok_or_else(|| format!("{} is not an rrs-admin export", path))?;
+
+    let known = data_tables(pool).await.map_err(|e| e.to_string())?;
+    let mut tables = vec![];
+    for entry in entries {
+        let table = entry["table"].as_str().unwrap_or_default();
+        if !known.iter().any(|name| name == table) {
+            return Err(format!(
+                "Unknown table {:?}, is the database migrated?",
+                table
+            ));
+        }
+        let kinds = column_kinds(pool, table).await.map_err(|e| e.to_string())?;
+        let rows = entry["rows"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // column names end up in the INSERT, so only the table's own are let through
+        let columns = rows
+            .iter()
+            .filter_map(Value::as_object)
+            .flat_map(|row| row.keys());
+        for column in columns {
+            if !kinds.contains_key(column) {
+                return Err(format!("Unknown column {:?} in table {:?}", column, table));
+            }
+        }
+        tables.push((table, kinds, rows));
+    }
+
+    let fail = |e: sqlx::Error| format!("Failed to import, nothing was changed: {}", e);
+    let mut tx = pool.begin().await.map_err(fail)?;
+    db::query(DEFER_FOREIGN_KEYS)
+        .execute(&mut tx)
+        .await
+        .map_err(fail)?;
+
+    // everything is cleared first, so cascades never reach rows that were just imported
+    for (table, _, _) in tables.iter().rev() {
+        db::query(&format!("DELETE FROM \"{}\"", table))
+            .execute(&mut tx)
+            .await
+            .map_err(fail)?;
+    }
+
+    for (table, kinds, rows) in &tables {
+        for row in rows.iter() {
+            let object = match row.as_object() {
+                Some(object) if !object.is_empty() => object,
+                _ => continue,
+            };
+            let columns: Vec<String> = object.keys().map(|name| format!("\"{}\"", name)).collect();
+            // the columns differ from row to row, so the sql isn't left in db::query's cache
+            let sql = db::numbered_placeholders(&format!(
+                "INSERT INTO \"{}\" ({}) VALUES ({})",
+                table,
+                columns.join(", "),
+                vec!["?"; columns.len()].join(", ")
+            ));
+            let mut query = sqlx::query(&sql);
+            for (name, value) in object {
+                query = kinds[name].bind(query, value);
+            }
+            query.execute(&mut tx).await.map_err(fail)?;
+        }
+
+        // ids were inserted as they are, so the sequence has to catch up with them
+        #[cfg(feature = "postgres")]
+        if kinds.contains_key("id") {
+            db::query(&format!(
+                "SELECT setval(pg_get_serial_sequence(?, 'id'), COALESCE(MAX(id), 0) + 1, false)
+                FROM \"{}\"",
+                table
+            ))
+            .bind(format!("\"{}\"", table))
+            .fetch_one(&mut tx)
+            .await
+            .map_err(fail)?;
+        }
+        println!("{}: {} rows", table, rows.len());
+    }
+
+    tx.commit().await.map_err(fail)
+}
+
+async fn vacuum(pool: &DbPool) -> AdminResult {
+    db::query("VACUUM")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to vacuum: {}", e))?;
+    println!("database vacuumed");
+    Ok(())
+}
+
+async fn seed_db(pool: &DbPool, args: &[String]) -> AdminResult {
+    let mut config = seed::SeedConfig::default();
+    for pair in args.chunks(2) {
+        let (flag, value) = match pair {
+            [flag, value] => (flag.as_str(), value),
+            _ => return Err(USAGE.to_string()),
+        };
+        let invalid = |_| format!("{} expects a number, got {}", flag, value);
+        match flag {
+            "--seed" => config.seed = value.parse().map_err(invalid)?,
+            "--users" => config.users = value.parse().map_err(invalid)?,
+            "--projects" => config.projects = value.parse().map_err(invalid)?,
+            "--participants" => config.participants = value.parse().map_err(invalid)?,
+            "--tasks" => config.tasks = value.parse().map_err(invalid)?,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut db = pool.acquire().await.map_err(|e| e.to_string())?;
+    match seed::seed(&mut db, &config).await {
+        Ok(Some(summary)) => {
+            println!(
+                "seeded {} users, {} projects and {} tasks, everyone's password is {:?}",
+                summary.users,
+                summary.projects,
+                summary.tasks,
+                seed::SEED_PASSWORD
+            );
+            Ok(())
+        }
+        Ok(None) => {
+            println!("already seeded, nothing to do");
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to seed: {}", e)),
+    }
+}
+
+async fn run(command: &str, args: &[String]) -> AdminResult {
+    let pool = connect().await?;
+    match command {
+        "create-user" => create_user(&pool, args).await,
+        "set-admin" => set_flag(&pool, "admin", args).await,
+        "set-premium" => set_flag(&pool, "premium", args).await,
+        "reset-password" => reset_password(&pool, args).await,
+        "list-users" => list_users(&pool).await,
+        "migrate" => migrate(&pool).await,
+        "export" => export(&pool, args).await,
+        "import" => import(&pool, args).await,
+        "vacuum" => vacuum(&pool).await,
+        "seed" => seed_db(&pool, args).await,
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+#[rocket::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    if command == "help" || command == "--help" {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    match run(command, args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
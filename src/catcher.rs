@@ -0,0 +1,114 @@
+// Error pages. Catchers and AppError both end up in ErrorPage, which renders the
+// error template, or JSON for clients that prefer it.
+use crate::request_id::RequestId;
+use crate::user::User;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::json;
+use rocket_dyn_templates::{context, Template};
+
+#[derive(Debug)]
+pub struct ErrorPage {
+    pub status: Status,
+    pub message: String,
+}
+
+impl ErrorPage {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        ErrorPage {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ErrorPage {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status.code;
+        let reason = self.status.reason().unwrap_or_default();
+        // only server errors need correlating with the log
+        let request_id = self
+            .status
+            .class()
+            .is_server_error()
+            .then(|| RequestId::of(request).to_string());
+
+        let wants_json = request
+            .accept()
+            .is_some_and(|accept| accept.preferred().is_json());
+        if wants_json {
+            let body = json!({
+                "status": status,
+                "reason": reason,
+                "message": self.message,
+                "request_id": request_id,
+            });
+            return (self.status, body).respond_to(request);
+        }
+
+        // the &User guard caches its lookup, so the header still shows who is logged in
+        let user = request.local_cache(|| None::<User>);
+        let context = context! {
+            user,
+            status,
+            reason,
+            message: self.message,
+            request_id,
+        };
+        (self.status, Template::render("error", context)).respond_to(request)
+    }
+}
+
+#[catch(401)]
+fn unauthorized() -> ErrorPage {
+    ErrorPage::new(Status::Unauthorized, "You need to log in to see this")
+}
+
+#[catch(403)]
+fn forbidden() -> ErrorPage {
+    ErrorPage::new(Status::Forbidden, "You don't have access to this")
+}
+
+#[catch(404)]
+fn not_found() -> ErrorPage {
+    ErrorPage::new(Status::NotFound, "There's nothing here")
+}
+
+#[catch(422)]
+fn unprocessable_entity() -> ErrorPage {
+    ErrorPage::new(
+        Status::UnprocessableEntity,
+        "We couldn't make sense of what was sent",
+    )
+}
+
+#[catch(500)]
+fn internal_error(request: &Request) -> ErrorPage {
+    error!(
+        "[{}] {} {} failed",
+        RequestId::of(request),
+        request.method(),
+        request.uri()
+    );
+    ErrorPage::new(
+        Status::InternalServerError,
+        "Something went wrong on our side",
+    )
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("catcher stage", |rocket| async {
+        rocket.register(
+            "/",
+            catchers![
+                unauthorized,
+                forbidden,
+                not_found,
+                unprocessable_entity,
+                internal_error
+            ],
+        )
+    })
+}
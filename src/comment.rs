@@ -0,0 +1,368 @@
+// rocket's FromForm derive still emits the removed private_in_public lint
+#![allow(renamed_and_removed_lints)]
+
+use crate::db::{self, DbRow};
+
+use crate::mention::{link_mentions, notify_mentions};
+use crate::user::{get_project_members, require_member, Db, User};
+use pulldown_cmark::{html, Options, Parser};
+use rocket::fairing::AdHoc;
+use rocket::form::{Contextual, Form};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket_db_pools::{sqlx, sqlx::Row, Connection};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Comment {
+    pub id: i64,
+    pub author: i64,
+    #[serde(skip_deserializing)]
+    pub author_name: String,
+    pub project_id: i64,
+    pub task_id: Option<i64>,
+    pub parent_id: Option<i64>,
+    pub body: String,
+    // sanitized html rendered from the markdown body
+    #[serde(skip_deserializing)]
+    pub body_html: String,
+    pub created: String,
+    pub edited: String,
+    pub deleted: bool,
+    // how many replies deep the comment sits in its thread
+    #[serde(skip_deserializing)]
+    pub depth: usize,
+}
+
+// every comment of the project from the url in thread order
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Comments(pub Vec<Comment>);
+
+// a user may comment on projects they own or participate in
+pub const PROJECT_MEMBER: &str = "
+    EXISTS (SELECT 1 FROM project p WHERE p.id = ?
+        AND (p.owner = ? OR (',' || p.participants || ',') LIKE ('%,' || ? || ',%')))";
+
+// markdown is rendered to html and then run through ammonia, which strips
+// scripts, event handlers and anything else that isn't plain formatting
+pub fn render_markdown(body: &str) -> String {
+    let mut unsafe_html = String::new();
+    let parser = Parser::new_ext(body, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES);
+    html::push_html(&mut unsafe_html, parser);
+    ammonia::clean(&unsafe_html)
+}
+
+fn serialize_comment(row: &DbRow) -> Comment {
+    let deleted: bool = row.get("deleted");
+    // deleted comments keep their place in the thread but lose their content
+    let body: String = if deleted {
+        String::new()
+    } else {
+        row.get("body")
+    };
+    Comment {
+        id: row.get("id"),
+        author: row.get("author"),
+        author_name: row.get("author_name"),
+        project_id: row.get("project_id"),
+        task_id: row.get("task_id"),
+        parent_id: row.get("parent_id"),
+        body_html: render_markdown(&body),
+        body,
+        created: row.get("created"),
+        edited: row.get("edited"),
+        deleted,
+        depth: 0,
+    }
+}
+
+// orders chronologically sorted comments so that replies directly follow the
+// comment they answer, siblings staying oldest first
+fn thread(comments: Vec<Comment>) -> Vec<Comment> {
+    fn walk(parent: Option<i64>, depth: usize, rest: &mut Vec<Comment>, out: &mut Vec<Comment>) {
+        while let Some(i) = rest.iter().position(|c| c.parent_id == parent) {
+            let mut comment = rest.remove(i);
+            comment.depth = depth;
+            let id = comment.id;
+            out.push(comment);
+            walk(Some(id), depth + 1, rest, out);
+        }
+    }
+
+    let mut rest = comments;
+    let mut out = Vec::with_capacity(rest.len());
+    walk(None, 0, &mut rest, &mut out);
+    out
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Comments {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<&User>().await {
+            Outcome::Success(user) => user,
+            _ => return Outcome::Forward(()),
+        };
+        // project id comes from the url, same as for ProjectTasks
+        let proj_id = request.param(1).unwrap().unwrap();
+        let mut db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("could not establish db connection");
+        // the thread is only for the project's members
+        if require_member(&mut db, user.id.unwrap(), proj_id, None)
+            .await
+            .is_err()
+        {
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+        match get_comments_for_project(db, proj_id).await {
+            Ok(comments) => Outcome::Success(Comments(comments)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+pub async fn get_comments_for_project(
+    mut db: Connection<Db>,
+    proj_id: i64,
+) -> Result<Vec<Comment>, String> {
+    let fail = |e: sqlx::Error| format!("Failed to get comments: {}", e);
+
+    let rows = db::query(
+        "SELECT c.*, u.name AS author_name FROM comment c
+        JOIN \"user\" u ON u.id = c.author
+        WHERE c.project_id = ?
+        ORDER BY c.created, c.id",
+    )
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await
+    .map_err(fail)?;
+    let members = get_project_members(&mut db, proj_id).await.map_err(fail)?;
+
+    let mut comments: Vec<Comment> = rows.iter().map(serialize_comment).collect();
+    for comment in comments.iter_mut() {
+        comment.body_html = link_mentions(&comment.body_html, &members);
+    }
+    Ok(thread(comments))
+}
+
+pub async fn add_comment(
+    mut db: Connection<Db>,
+    author: i64,
+    proj_id: i64,
+    task_id: Option<i64>,
+    parent_id: Option<i64>,
+    body: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    // the task and the replied-to comment have to belong to the same project
+    let inserted = db::query(&format!(
+        "INSERT INTO comment (author, project_id, task_id, parent_id, body, created)
+        SELECT ?, ?, ?, ?, ?, ?
+        WHERE {}
+        AND (?3 IS NULL OR EXISTS (SELECT 1 FROM proj_tasks WHERE id = ?3 AND owner_proj = ?2))
+        AND (?4 IS NULL OR EXISTS (SELECT 1 FROM comment WHERE id = ?4 AND project_id = ?2
+            AND (task_id = ?3 OR (task_id IS NULL AND ?3 IS NULL))))
+        RETURNING id",
+        PROJECT_MEMBER
+    ))
+    .bind(author)
+    .bind(proj_id)
+    .bind(task_id)
+    .bind(parent_id)
+    .bind(body)
+    .bind(db::now())
+    .bind(proj_id)
+    .bind(author)
+    .bind(author)
+    .fetch_optional(&mut *db)
+    .await?;
+    let id: i64 = match inserted {
+        Some(row) => row.get("id"),
+        None => return Ok(None),
+    };
+
+    let members = get_project_members(&mut db, proj_id).await?;
+    let link = format!("/project/{}", proj_id);
+    notify_mentions(&mut db, &members, author, body, "", "a comment", &link).await?;
+
+    Ok(Some(id))
+}
+
+// only the author may edit their comment, and only through its own project
+pub async fn edit_comment(
+    mut db: Connection<Db>,
+    author: i64,
+    proj_id: i64,
+    id: i64,
+    body: &str,
+) -> Result<Option<()>, sqlx::Error> {
+    let row = db::query("SELECT body FROM comment WHERE id = ? AND author = ? AND project_id = ?")
+        .bind(id)
+        .bind(author)
+        .bind(proj_id)
+        .fetch_optional(&mut *db)
+        .await?;
+    let previous: String = match row {
+        Some(row) => row.get("body"),
+        None => return Ok(None),
+    };
+
+    let result = db::query(
+        "UPDATE comment SET body = ?, edited = ?
+        WHERE id = ? AND author = ? AND project_id = ? AND NOT deleted",
+    )
+    .bind(body)
+    .bind(db::now())
+    .bind(id)
+    .bind(author)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+    if result.rows_affected() != 1 {
+        return Ok(None);
+    }
+
+    let members = get_project_members(&mut db, proj_id).await?;
+    let link = format!("/project/{}", proj_id);
+    notify_mentions(
+        &mut db,
+        &members,
+        author,
+        body,
+        &previous,
+        "a comment",
+        &link,
+    )
+    .await?;
+
+    Ok(Some(()))
+}
+
+// comments are only marked deleted so that the replies below them stay in place
+pub async fn delete_comment_db(
+    mut db: Connection<Db>,
+    author: i64,
+    proj_id: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query(
+        "UPDATE comment SET deleted = TRUE, body = ''
+        WHERE id = ? AND author = ? AND project_id = ? AND NOT deleted",
+    )
+    .bind(id)
+    .bind(author)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+// `?task=<id>` narrows the thread down to the comments on one task
+#[get("/project/<_proj_id>/comments?<task>")]
+async fn comments_api(
+    _user: &User,
+    comments: Comments,
+    _proj_id: i64,
+    task: Option<i64>,
+) -> Json<Vec<Comment>> {
+    let mut comments = comments.0;
+    if task.is_some() {
+        comments.retain(|c| c.task_id == task);
+    }
+    Json(comments)
+}
+
+#[derive(FromForm, Debug)]
+struct CommentForm<'v> {
+    #[field(validate = len(1..=10000))]
+    body: &'v str,
+    task_id: Option<i64>,
+    parent_id: Option<i64>,
+}
+
+#[post("/project/<proj_id>/comment", data = "<form>")]
+async fn add_comment_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, CommentForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Comments can't be empty"),
+    };
+    let body = form_data.body.trim();
+    if body.is_empty() {
+        return Flash::error(project, "Comments can't be empty");
+    }
+    let result = add_comment(
+        db,
+        user.id.unwrap(),
+        proj_id,
+        form_data.task_id,
+        form_data.parent_id,
+        body,
+    )
+    .await;
+    match result {
+        Ok(Some(_)) => Flash::success(project, "Comment added"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[derive(FromForm, Debug)]
+struct EditCommentForm<'v> {
+    #[field(validate = len(1..=10000))]
+    body: &'v str,
+}
+
+#[post("/project/<proj_id>/comment/<id>/edit", data = "<form>")]
+async fn edit_comment_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, EditCommentForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Comments can't be empty"),
+    };
+    match edit_comment(db, user.id.unwrap(), proj_id, id, form_data.body.trim()).await {
+        Ok(Some(_)) => Flash::success(project, "Comment updated"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/comment/<id>")]
+async fn delete_comment(db: Connection<Db>, user: &User, proj_id: i64, id: i64) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    match delete_comment_db(db, user.id.unwrap(), proj_id, id).await {
+        Ok(Some(_)) => Flash::success(project, "Comment deleted"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("comment stage", |rocket| async {
+        rocket.mount(
+            "/",
+            routes![
+                add_comment_post,
+                comments_api,
+                delete_comment,
+                edit_comment_post,
+            ],
+        )
+    })
+}
@@ -0,0 +1,182 @@
+// The database backend, picked at build time: SQLite by default, Postgres with the
+// `postgres` feature. Queries are written once, in the SQL both understand, with
+// sqlite's `?` placeholders; `query` numbers those for both. The few things the
+// dialects don't share, like the current time, come from the functions below.
+//
+// `cargo test` runs against a fresh SQLite database per test. The same suite runs
+// against Postgres with the feature and a server to create its databases on:
+//   TEST_DATABASE_URL=postgres://postgres@localhost:5432/rrs_test \
+//       cargo test --workspace --features postgres
+// nothing runs that for you, so do it before merging changes to SQL or migrations.
+use chrono::{DateTime, Utc};
+use rocket_db_pools::sqlx::{self, database::HasArguments, migrate::Migrator, query::Query};
+use std::collections::HashMap;
+use tera::Value;
+
+#[cfg(not(feature = "postgres"))]
+pub use sqlx::sqlite::{
+    Sqlite as Backend, SqliteConnection as DbConnection, SqlitePool as DbPool, SqliteRow as DbRow,
+};
+
+#[cfg(feature = "postgres")]
+pub use sqlx::postgres::{
+    PgConnection as DbConnection, PgPool as DbPool, PgRow as DbRow, Postgres as Backend,
+};
+
+pub type DbQuery<'q> = Query<'q, Backend, <Backend as HasArguments<'q>>::Arguments>;
+
+// embedded at build time, /readyz compares it against what the database has applied
+#[cfg(not(feature = "postgres"))]
+pub static MIGRATOR: Migrator = sqlx::migrate!("db/migrations");
+
+#[cfg(feature = "postgres")]
+pub static MIGRATOR: Migrator = sqlx::migrate!("db/migrations-postgres");
+
+// how many distinct queries `query` keeps, far more than the code base has
+const MAX_QUERIES: usize = 1024;
+
+// sqlx binds sqlite's bare `?` by position and `?n` by number, and loses count
+// when a query mixes the two, which the shared PROJECT_MEMBER check makes common.
+// numbering every placeholder avoids that on sqlite and is what postgres needs.
+//
+// sqlx only borrows the sql, so each rewritten query is kept for the life of the
+// process. that is fine for queries built from constants, there are only so many
+// of them; sql built from data, like rrs-admin's inserts, has to keep the string
+// from `numbered_placeholders` itself. running into MAX_QUERIES means some query
+// was built from data anyway, which panics rather than growing without end
+pub fn query<'q>(sql: &str) -> DbQuery<'q> {
+    use std::sync::{Mutex, OnceLock, PoisonError};
+
+    static REWRITTEN: OnceLock<Mutex<HashMap<String, &'static str>>> = OnceLock::new();
+    let mut rewritten = REWRITTEN
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let sql = match rewritten.get(sql) {
+        Some(sql) => *sql,
+        None => {
+            assert!(
+                rewritten.len() < MAX_QUERIES,
+                "more than {} distinct queries, is sql being built from data? {}",
+                MAX_QUERIES,
+                sql
+            );
+            let numbered: &'static str = Box::leak(numbered_placeholders(sql).into_boxed_str());
+            rewritten.insert(sql.to_string(), numbered);
+            numbered
+        }
+    };
+    sqlx::query(sql)
+}
+
+// `?` becomes `$n` and `?n` becomes `$n`, numbered the way sqlite numbers them:
+// a bare `?` is one more than the highest number so far
+pub fn numbered_placeholders(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len() + 8);
+    let mut chars = sql.chars().peekable();
+    let mut highest = 0;
+    // inside '...' or "...", where a ? is just a character
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '?') => {
+                let mut digits = String::new();
+                while let Some(&d) = chars.peek() {
+                    if !d.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(d);
+                    chars.next();
+                }
+                let n = digits.parse().unwrap_or(highest + 1);
+                highest = highest.max(n);
+                out.push('$');
+                out.push_str(&n.to_string());
+                continue;
+            }
+            (None, _) => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+// dates are stored as 'YYYY-MM-DD HH:MM:SS' text in UTC on both backends, so they
+// compare as strings; these produce values in that format, in sql and in rust
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub fn timestamp(at: DateTime<Utc>) -> String {
+    at.format(TIMESTAMP_FORMAT).to_string()
+}
+
+pub fn now() -> String {
+    timestamp(Utc::now())
+}
+
+// tera's `date` filter doesn't read the stored format, this one shows a stored date
+// with `format`, as in `{{ task.task_start_date | timestamp(format="%v %X") }}`
+pub fn timestamp_filter(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    use std::fmt::Write;
+
+    let value = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("timestamp expects a string"))?;
+    let format = args
+        .get("format")
+        .and_then(Value::as_str)
+        .unwrap_or(TIMESTAMP_FORMAT);
+    let at = chrono::NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+        .map_err(|e| tera::Error::msg(format!("{:?} is not a stored date: {}", value, e)))?;
+    // a bad format string fails to write instead of panicking in to_string
+    let mut shown = String::new();
+    write!(shown, "{}", at.format(format))
+        .map_err(|_| tera::Error::msg(format!("invalid date format {:?}", format)))?;
+    Ok(Value::String(shown))
+}
+
+#[cfg(not(feature = "postgres"))]
+pub const NOW: &str = "datetime('now')";
+#[cfg(feature = "postgres")]
+pub const NOW: &str = "to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')";
+
+// just the date, which sorts before every timestamp of the day: `>= TODAY` is today on
+#[cfg(not(feature = "postgres"))]
+pub const TODAY: &str = "date('now')";
+#[cfg(feature = "postgres")]
+pub const TODAY: &str = "to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD')";
+
+// `timestamp` moved by `modifier`, an expression giving e.g. '-7 days'
+#[cfg(not(feature = "postgres"))]
+pub fn shifted(timestamp: &str, modifier: &str) -> String {
+    format!("datetime({}, {})", timestamp, modifier)
+}
+#[cfg(feature = "postgres")]
+pub fn shifted(timestamp: &str, modifier: &str) -> String {
+    format!(
+        "to_char(CAST({} AS timestamp) + CAST({} AS interval), 'YYYY-MM-DD HH24:MI:SS')",
+        timestamp, modifier
+    )
+}
+
+// seconds since the epoch
+#[cfg(not(feature = "postgres"))]
+pub fn epoch_seconds(timestamp: &str) -> String {
+    format!("CAST(strftime('%s', {}) AS BIGINT)", timestamp)
+}
+#[cfg(feature = "postgres")]
+pub fn epoch_seconds(timestamp: &str) -> String {
+    format!(
+        "CAST(EXTRACT(EPOCH FROM CAST({} AS timestamp)) AS BIGINT)",
+        timestamp
+    )
+}
+
+// on-disk size of the database in bytes, as `size`
+#[cfg(not(feature = "postgres"))]
+pub const DB_SIZE: &str =
+    "SELECT page_count * page_size AS size FROM pragma_page_count(), pragma_page_size()";
+#[cfg(feature = "postgres")]
+pub const DB_SIZE: &str = "SELECT pg_database_size(current_database()) AS size";
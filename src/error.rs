@@ -0,0 +1,100 @@
+// The error type of the data-access functions in user.rs and the handlers built
+// on them. Helpers taking a `&mut DbConnection` keep returning sqlx::Error so
+// they compose inside transactions; `?` turns that into an AppError.
+use crate::catcher::ErrorPage;
+use crate::request_id::RequestId;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AppError {
+    // the row a request refers to doesn't exist
+    NotFound(String),
+    // logged in, but not allowed to touch this
+    Forbidden(String),
+    // the change clashes with what is already there, e.g. a duplicate or a full column
+    Conflict(String),
+    // the input is well-formed but not acceptable
+    Validation(String),
+    Database(sqlx::Error),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn status(&self) -> Status {
+        match self {
+            AppError::NotFound(_) => Status::NotFound,
+            AppError::Forbidden(_) => Status::Forbidden,
+            AppError::Conflict(_) => Status::Conflict,
+            AppError::Validation(_) => Status::UnprocessableEntity,
+            AppError::Database(_) => Status::InternalServerError,
+        }
+    }
+}
+
+// for UPDATEs and DELETEs, where touching no row means `what` wasn't there
+pub fn affected(rows: u64, what: &str) -> AppResult<()> {
+    match rows {
+        0 => Err(AppError::NotFound(format!("{} not found", what))),
+        _ => Ok(()),
+    }
+}
+
+// what the user gets to see; database errors stay in the log
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Forbidden(message)
+            | AppError::Conflict(message)
+            | AppError::Validation(message) => write!(f, "{}", message),
+            AppError::Database(_) => write!(f, "Something went wrong on our side"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        // sqlite's extended result codes and postgres' SQLSTATEs tell the constraint
+        // violations apart
+        let code = match &e {
+            sqlx::Error::Database(db) => db.code().map(|code| code.into_owned()),
+            _ => None,
+        };
+        match (e, code.as_deref()) {
+            (sqlx::Error::RowNotFound, _) => AppError::NotFound("Not found".to_string()),
+            // SQLITE_CONSTRAINT_UNIQUE and SQLITE_CONSTRAINT_PRIMARYKEY, unique_violation
+            (_, Some("2067" | "1555" | "23505")) => {
+                AppError::Conflict("That already exists".to_string())
+            }
+            // SQLITE_CONSTRAINT_FOREIGNKEY, foreign_key_violation
+            (_, Some("787" | "23503")) => {
+                AppError::Conflict("That refers to something that doesn't exist".to_string())
+            }
+            // SQLITE_CONSTRAINT_CHECK and SQLITE_CONSTRAINT_NOTNULL, check_violation and
+            // not_null_violation
+            (_, Some("275" | "1299" | "23514" | "23502")) => {
+                AppError::Validation("That value isn't allowed".to_string())
+            }
+            (e, _) => AppError::Database(e),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AppError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let id = RequestId::of(request);
+        match &self {
+            AppError::Database(e) => {
+                error!("[{}] {} {}: {}", id, request.method(), request.uri(), e)
+            }
+            e => info!("[{}] {} {}: {}", id, request.method(), request.uri(), e),
+        }
+        ErrorPage::new(self.status(), self.to_string()).respond_to(request)
+    }
+}
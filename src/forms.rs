@@ -0,0 +1,123 @@
+// The forms posted to the handlers in lib.rs. They live in a module of their own
+// because rocket's FromForm derive still emits the removed private_in_public lint
+// next to each form, where only a module level allow reaches it.
+#![allow(renamed_and_removed_lints)]
+
+use crate::user::parse_date;
+
+#[derive(FromForm, Debug)]
+pub struct UserRegistrationForm<'v> {
+    pub email: &'v str,
+    pub name: &'v str,
+    pub password: &'v str,
+    pub password_check: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct LoginForm<'v> {
+    pub email: &'v str,
+    pub password: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct EditProjectForm<'v> {
+    pub name: &'v str,
+    pub end_date: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct ParticipantForm<'v> {
+    pub email: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct ChecklistItemForm<'v> {
+    #[field(validate = len(1..))]
+    pub text: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct DependencyForm {
+    pub blocked_by: i64,
+}
+
+#[derive(FromForm, Debug)]
+pub struct EstimateForm {
+    pub hours: Option<f64>,
+}
+
+#[derive(FromForm, Debug)]
+pub struct AddProjectForm<'v> {
+    pub name: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct AddTaskForm<'v> {
+    pub description: &'v str,
+    #[field(validate = range(0..=3), default = 2)]
+    pub priority: i64,
+    pub due_date: &'v str,
+    pub parent_id: Option<i64>,
+    // an RFC 5545 RRULE like FREQ=WEEKLY;BYDAY=MO, makes the task recurring
+    pub rrule: Option<&'v str>,
+}
+
+fn validate_due_date<'v>(due_date: &&str) -> rocket::form::Result<'v, ()> {
+    if !due_date.is_empty() && parse_date(due_date).is_none() {
+        Err(rocket::form::Error::validation("invalid due date"))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+pub struct EditTaskForm<'v> {
+    #[field(validate = len(1..))]
+    pub description: &'v str,
+    #[field(validate = range(0..=3))]
+    pub priority: i64,
+    #[field(validate = validate_due_date())]
+    pub due_date: &'v str,
+    pub assignee: Option<i64>,
+    pub owner_proj: i64,
+}
+
+fn validate_timestamp<'v>(date: &&str) -> rocket::form::Result<'v, ()> {
+    if parse_date(date).is_none() {
+        Err(rocket::form::Error::validation("invalid date"))?;
+    }
+    Ok(())
+}
+
+fn not_before<'v, S: AsRef<str>>(end: &&str, start: S) -> rocket::form::Result<'v, ()> {
+    if end.is_empty() {
+        return Ok(());
+    }
+    match (parse_date(start.as_ref()), parse_date(end)) {
+        (Some(_), None) => Err(rocket::form::Error::validation("invalid date"))?,
+        (Some(start), Some(end)) if end < start => Err(rocket::form::Error::validation(
+            "end must not be before start",
+        ))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+pub struct EditTaskTimesForm<'v> {
+    #[field(validate = validate_timestamp())]
+    pub task_start_date: &'v str,
+    #[field(validate = not_before(self.task_start_date))]
+    pub task_end_date: &'v str,
+}
+
+#[derive(FromForm, Debug)]
+pub struct MoveTaskForm<'v> {
+    pub status: &'v str,
+    pub position: i64,
+}
+
+#[derive(FromForm, Debug)]
+pub struct WipLimitForm<'v> {
+    pub status: &'v str,
+    pub wip_limit: Option<i64>,
+}
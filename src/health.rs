@@ -0,0 +1,80 @@
+// Probes for the orchestrator. /healthz only says the process answers, /readyz
+// says whether it can do useful work: the database is reachable and migrated.
+use crate::db::{self, DbPool, MIGRATOR};
+use crate::user::Db;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::time::{timeout, Duration};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, Row};
+
+// a probe that hangs is as bad as one that fails
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[get("/healthz")]
+fn healthz() -> Json<Value> {
+    Json(json!({"status": "ok"}))
+}
+
+async fn check_db(pool: &DbPool) -> Result<(i64, Vec<i64>), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let size: i64 = db::query(db::DB_SIZE)
+        .fetch_one(&mut conn)
+        .await?
+        .get("size");
+    let applied = db::query("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(&mut conn)
+        .await?
+        .iter()
+        .map(|row| row.get("version"))
+        .collect();
+
+    Ok((size, applied))
+}
+
+#[get("/readyz")]
+async fn readyz(db: &State<Db>) -> (Status, Json<Value>) {
+    let version = env!("CARGO_PKG_VERSION");
+    let (size, applied) = match timeout(DB_TIMEOUT, check_db(db)).await {
+        Ok(Ok(checked)) => checked,
+        Ok(Err(e)) => {
+            error!("readiness: database check failed: {}", e);
+            let body =
+                json!({"status": "unavailable", "version": version, "database": "unreachable"});
+            return (Status::ServiceUnavailable, Json(body));
+        }
+        Err(_) => {
+            error!("readiness: database check timed out");
+            let body = json!({"status": "unavailable", "version": version, "database": "timeout"});
+            return (Status::ServiceUnavailable, Json(body));
+        }
+    };
+
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    let (status, state) = match pending.is_empty() {
+        true => (Status::Ok, "ready"),
+        false => (Status::ServiceUnavailable, "migrating"),
+    };
+    let body = json!({
+        "status": state,
+        "version": version,
+        "database": "ok",
+        "db_size_bytes": size,
+        "migrations": {
+            "applied": applied.len(),
+            "pending": pending,
+        },
+    });
+    (status, Json(body))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("health stage", |rocket| async {
+        rocket.mount("/", routes![healthz, readyz])
+    })
+}
@@ -0,0 +1,540 @@
+// In-process background jobs. Jobs live in the `job` table so they survive
+// restarts; recurring ones carry a cron schedule, one-off ones just a run_at.
+// A single runner started on liftoff claims due jobs one at a time and drains
+// the job in flight before Rocket shuts down.
+use crate::db::{self, DbConnection, DbPool, DbRow};
+use crate::live::Live;
+use crate::mail::{send_digests, send_notification_emails, Mailer};
+use crate::recurrence::advance_series;
+use crate::reminder::send_due_reminders;
+use crate::user::Db;
+use crate::webhook::send_due_deliveries;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use rocket::fairing::AdHoc;
+use rocket::futures::FutureExt;
+use rocket::serde::Serialize;
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::{interval, timeout, Duration as Interval};
+use rocket::tokio::{select, spawn};
+use rocket::State;
+use rocket_db_pools::{sqlx, sqlx::Row, Database};
+
+// one-off jobs are retried with backoff until they have failed this often
+const MAX_ATTEMPTS: i64 = 5;
+// jobs still marked running after this long were cut off by a crash
+const STALE_MINUTES: i64 = 30;
+// how long shutdown waits for the job in flight
+const DRAIN_SECONDS: u64 = 30;
+
+// the recurring jobs, seeded on every start: (name, kind, cron schedule)
+const RECURRING_JOBS: [(&str, &str, &str); 6] = [
+    ("webhook deliveries", "webhook.deliver", "* * * * *"),
+    ("notification emails", "mail.notifications", "* * * * *"),
+    ("task reminders", "reminder.send", "* * * * *"),
+    ("recurring tasks", "recurrence.advance", "* * * * *"),
+    ("daily digest", "mail.digest", "0 7 * * *"),
+    ("cleanup", "cleanup", "30 3 * * *"),
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Job {
+    pub id: i64,
+    // unique for recurring jobs, empty for one-off ones
+    pub name: String,
+    // decides what run_job does with the job
+    pub kind: String,
+    pub payload: String,
+    // cron expression, empty for one-off jobs
+    pub schedule: String,
+    pub run_at: String,
+    // 'scheduled', 'running', 'done' or 'failed'
+    pub status: String,
+    pub attempts: i64,
+    pub last_error: String,
+}
+
+fn serialize_job(row: &DbRow) -> Job {
+    Job {
+        id: row.get("id"),
+        name: row.get("name"),
+        kind: row.get("kind"),
+        payload: row.get("payload"),
+        schedule: row.get("schedule"),
+        run_at: row.get("run_at"),
+        status: row.get("status"),
+        attempts: row.get("attempts"),
+        last_error: row.get("last_error"),
+    }
+}
+
+// a classic five field cron expression: minute hour day-of-month month day-of-week.
+// every field takes `*`, `*/step`, `a`, `a-b`, `a-b/step` and comma separated lists
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    // day-of-month and day-of-week are or-ed when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, String> {
+    let mut values = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("bad step in {}", part))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start
+                        .parse()
+                        .map_err(|_| format!("bad value in {}", part))?,
+                    end.parse().map_err(|_| format!("bad value in {}", part))?,
+                ),
+                None => {
+                    let value = range
+                        .parse()
+                        .map_err(|_| format!("bad value in {}", part))?;
+                    (value, if step > 1 { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} is out of range {}-{}", part, min, max));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 cron fields, got {}", fields.len()));
+        }
+        // sunday may be written as 0 or 7
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        if weekdays.contains(&7) {
+            weekdays.retain(|day| *day != 7);
+            if !weekdays.contains(&0) {
+                weekdays.insert(0, 0);
+            }
+        }
+        Ok(Cron {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days.contains(&time.day());
+        let weekday = self
+            .weekdays
+            .contains(&time.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        day_matches
+            && self.minutes.contains(&time.minute())
+            && self.hours.contains(&time.hour())
+            && self.months.contains(&time.month())
+    }
+
+    // the first matching minute strictly after `after`, looking at most 5 years ahead
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = after + Duration::days(5 * 366);
+        while time < limit {
+            if !self.months.contains(&time.month()) {
+                // jump to the first minute of the next month
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+                continue;
+            }
+            if self.matches(&time) {
+                return Some(time);
+            }
+            time += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+// queues a one-off job of `kind` to run at `run_at`
+pub async fn schedule_once(
+    db: &mut DbConnection,
+    kind: &str,
+    payload: &str,
+    run_at: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    let row = db::query(
+        "INSERT INTO job (kind, payload, run_at, created) VALUES (?, ?, ?, ?) RETURNING id",
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(db::timestamp(run_at))
+    .bind(db::now())
+    .fetch_one(db)
+    .await?;
+
+    Ok(row.get("id"))
+}
+
+// makes sure every recurring job exists and follows its current schedule. runs
+// before the runner starts, so a recurring job still marked running was cut off
+// along with the previous process and goes back to the queue
+async fn seed_recurring_jobs(pool: &DbPool) -> Result<(), String> {
+    for (name, kind, schedule) in RECURRING_JOBS {
+        let cron = Cron::parse(schedule).map_err(|e| format!("{}: {}", name, e))?;
+        let run_at = cron
+            .next_after(Utc::now())
+            .ok_or_else(|| format!("{} never runs", name))?;
+        db::query(
+            "INSERT INTO job (name, kind, schedule, run_at, created) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (name) WHERE name <> '' DO UPDATE
+            SET kind = excluded.kind, schedule = excluded.schedule,
+                status = 'scheduled', locked_at = NULL,
+                run_at = CASE WHEN job.schedule = excluded.schedule THEN job.run_at ELSE excluded.run_at END",
+        )
+        .bind(name)
+        .bind(kind)
+        .bind(schedule)
+        .bind(db::timestamp(run_at))
+        .bind(db::now())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to seed job {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+// hands jobs that were running when a runner went down back to the queue,
+// checked on every tick so one-off jobs come back without another restart
+async fn recover_stale_jobs(pool: &DbPool) -> Result<u64, sqlx::Error> {
+    let result = db::query(&format!(
+        "UPDATE job SET status = 'scheduled'
+        WHERE status = 'running' AND locked_at < {}",
+        db::shifted(db::NOW, "?")
+    ))
+    .bind(format!("-{} minutes", STALE_MINUTES))
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// claims the most overdue job. the status check in the UPDATE makes sure only one
+// runner gets it even if several processes share the database
+async fn claim_job(pool: &DbPool) -> Result<Option<Job>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let job = match db::query(&format!(
+        "SELECT * FROM job WHERE status = 'scheduled' AND run_at <= {}
+        ORDER BY run_at, id LIMIT 1",
+        db::NOW
+    ))
+    .fetch_optional(&mut tx)
+    .await?
+    {
+        Some(row) => serialize_job(&row),
+        None => return Ok(None),
+    };
+    let claimed = db::query(&format!(
+        "UPDATE job SET status = 'running', locked_at = {}, attempts = attempts + 1
+        WHERE id = ? AND status = 'scheduled'",
+        db::NOW
+    ))
+    .bind(job.id)
+    .execute(&mut tx)
+    .await?;
+    if claimed.rows_affected() != 1 {
+        return Ok(None);
+    }
+
+    tx.commit().await?;
+    Ok(Some(Job {
+        status: "running".to_string(),
+        attempts: job.attempts + 1,
+        ..job
+    }))
+}
+
+// records how the run went and works out when the job runs next
+async fn finish_job(
+    pool: &DbPool,
+    job: &Job,
+    result: Result<(), String>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let last_error = result.err().unwrap_or_default();
+
+    let (status, run_at, attempts) = if !job.schedule.is_empty() {
+        // recurring jobs simply wait for their next turn
+        let next = Cron::parse(&job.schedule)
+            .ok()
+            .and_then(|cron| cron.next_after(now));
+        match next {
+            Some(next) => ("scheduled", next, 0),
+            None => ("failed", now, job.attempts),
+        }
+    } else if last_error.is_empty() {
+        ("done", now, job.attempts)
+    } else if job.attempts < MAX_ATTEMPTS {
+        let backoff = Duration::minutes(1 << (job.attempts - 1).clamp(0, 10));
+        ("scheduled", now + backoff, job.attempts)
+    } else {
+        ("failed", now, job.attempts)
+    };
+
+    db::query(
+        "UPDATE job SET status = ?, run_at = ?, attempts = ?, last_error = ?, locked_at = NULL
+        WHERE id = ?",
+    )
+    .bind(status)
+    .bind(db::timestamp(run_at))
+    .bind(attempts)
+    .bind(last_error)
+    .bind(job.id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// removes what nobody needs anymore: old read notifications, settled webhook
+// deliveries and finished one-off jobs. logins live in private cookies, so
+// there are no server side sessions to expire
+async fn cleanup(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let month_ago = db::timestamp(Utc::now() - Duration::days(30));
+    db::query("DELETE FROM notification WHERE read AND created < ?")
+        .bind(&month_ago)
+        .execute(pool)
+        .await?;
+    db::query("DELETE FROM webhook_delivery WHERE status <> 'pending' AND created < ?")
+        .bind(&month_ago)
+        .execute(pool)
+        .await?;
+    db::query(&format!(
+        "DELETE FROM job WHERE schedule = '' AND status IN ('done', 'failed')
+        AND run_at < {}",
+        db::shifted(db::NOW, "'-7 days'")
+    ))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// everything a job might need, built once on liftoff
+#[derive(Clone)]
+pub struct JobContext {
+    pub pool: DbPool,
+    pub mailer: Mailer,
+    pub client: reqwest::Client,
+    pub live: Live,
+}
+
+async fn run_job(context: &JobContext, job: &Job) -> Result<(), String> {
+    let pool = &context.pool;
+    match job.kind.as_str() {
+        "webhook.deliver" => send_due_deliveries(pool, &context.client)
+            .await
+            .map_err(|e| format!("Failed to send webhook deliveries: {}", e)),
+        "mail.notifications" => send_notification_emails(pool, &context.mailer).await,
+        "reminder.send" => send_due_reminders(pool, &context.mailer).await,
+        "recurrence.advance" => advance_series(pool, &context.live)
+            .await
+            .map_err(|e| format!("Failed to advance recurring tasks: {}", e)),
+        "mail.digest" => send_digests(pool, &context.mailer).await,
+        "cleanup" => cleanup(pool)
+            .await
+            .map_err(|e| format!("Failed to clean up: {}", e)),
+        kind => Err(format!("unknown job kind: {}", kind)),
+    }
+}
+
+// the runner task, awaited on shutdown so the job in flight can finish
+pub struct JobRunner(Mutex<Option<JoinHandle<()>>>);
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("job stage", |rocket| async {
+        rocket
+            .manage(JobRunner(Mutex::new(None)))
+            .attach(AdHoc::on_liftoff("job runner", |rocket| {
+                Box::pin(async move {
+                    let pool: DbPool = match Db::fetch(rocket) {
+                        Some(db) => (**db).clone(),
+                        None => {
+                            error!("job runner: database is not initialized");
+                            return;
+                        }
+                    };
+                    if let Err(e) = seed_recurring_jobs(&pool).await {
+                        error!("{}", e);
+                    }
+
+                    let mailer: &State<Mailer> = State::get(rocket).expect("mailer is managed");
+                    let live: &State<Live> = State::get(rocket).expect("live is managed");
+                    let context = JobContext {
+                        pool,
+                        mailer: mailer.inner().clone(),
+                        client: reqwest::Client::builder()
+                            .timeout(Interval::from_secs(10))
+                            .build()
+                            .expect("could not build the http client"),
+                        live: live.inner().clone(),
+                    };
+                    let mut shutdown = rocket.shutdown();
+
+                    let handle = spawn(async move {
+                        let mut ticks = interval(Interval::from_secs(5));
+                        'runner: loop {
+                            select! {
+                                _ = ticks.tick() => {}
+                                _ = &mut shutdown => break,
+                            }
+                            match recover_stale_jobs(&context.pool).await {
+                                Ok(0) => {}
+                                Ok(recovered) => {
+                                    info!("job runner: requeued {} stale jobs", recovered)
+                                }
+                                Err(e) => error!("Failed to recover stale jobs: {}", e),
+                            }
+                            // work through everything that is due before waiting again,
+                            // unless a shutdown was requested in the meantime
+                            loop {
+                                if (&mut shutdown).now_or_never().is_some() {
+                                    break 'runner;
+                                }
+                                let job = match claim_job(&context.pool).await {
+                                    Ok(Some(job)) => job,
+                                    Ok(None) => break,
+                                    Err(e) => {
+                                        error!("Failed to claim a job: {}", e);
+                                        break;
+                                    }
+                                };
+                                let result = run_job(&context, &job).await;
+                                if let Err(e) = &result {
+                                    error!("job {} ({}) failed: {}", job.id, job.kind, e);
+                                }
+                                if let Err(e) = finish_job(&context.pool, &job, result).await {
+                                    error!("Failed to finish job {}: {}", job.id, e);
+                                }
+                            }
+                        }
+                        info!("job runner: stopped");
+                    });
+
+                    let runner: &State<JobRunner> =
+                        State::get(rocket).expect("job runner is managed");
+                    *runner.0.lock().await = Some(handle);
+                })
+            }))
+            .attach(AdHoc::on_shutdown("job drain", |rocket| {
+                Box::pin(async move {
+                    let runner: &State<JobRunner> =
+                        State::get(rocket).expect("job runner is managed");
+                    if let Some(handle) = runner.0.lock().await.take() {
+                        if timeout(Interval::from_secs(DRAIN_SECONDS), handle)
+                            .await
+                            .is_err()
+                        {
+                            error!("job runner: gave up waiting for the job in flight");
+                        }
+                    }
+                })
+            }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        Utc.datetime_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_lists_ranges_and_steps() {
+        let cron = Cron::parse("*/15 9-11 1,15 * 1-5").unwrap();
+        assert_eq!(cron.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(cron.hours, vec![9, 10, 11]);
+        assert_eq!(cron.days, vec![1, 15]);
+        assert_eq!(cron.weekdays, vec![1, 2, 3, 4, 5]);
+        // sunday as 7 is folded into 0
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, vec![0]);
+
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+    }
+
+    #[test]
+    fn finds_the_next_matching_minute() {
+        let every_minute = Cron::parse("* * * * *").unwrap();
+        assert_eq!(
+            every_minute.next_after(Utc.with_ymd_and_hms(2023, 6, 1, 7, 0, 30).unwrap()),
+            Some(at("2023-06-01 07:01"))
+        );
+
+        let daily = Cron::parse("0 7 * * *").unwrap();
+        assert_eq!(
+            daily.next_after(at("2023-06-01 07:00")),
+            Some(at("2023-06-02 07:00"))
+        );
+
+        // restricted day-of-month and day-of-week are or-ed: the 13th or a friday
+        let friday_or_13th = Cron::parse("0 0 13 * 5").unwrap();
+        assert_eq!(
+            friday_or_13th.next_after(at("2023-06-01 00:00")),
+            Some(at("2023-06-02 00:00"))
+        );
+        assert_eq!(
+            friday_or_13th.next_after(at("2023-06-10 00:00")),
+            Some(at("2023-06-13 00:00"))
+        );
+
+        // months that don't match are skipped as a whole
+        let new_year = Cron::parse("0 0 1 1 *").unwrap();
+        assert_eq!(
+            new_year.next_after(at("2023-06-01 00:00")),
+            Some(at("2024-01-01 00:00"))
+        );
+        // the 30th of february never comes
+        assert_eq!(
+            Cron::parse("0 0 30 2 *")
+                .unwrap()
+                .next_after(at("2023-06-01 00:00")),
+            None
+        );
+    }
+}
@@ -0,0 +1,583 @@
+// FromForm wraps the default colour in .into() and still emits the removed
+// private_in_public lint
+#![allow(clippy::useless_conversion, renamed_and_removed_lints)]
+
+use crate::db::{self, DbConnection, DbRow};
+use crate::get_flash_msg;
+use crate::user::{require_member, Db, User};
+use rocket::fairing::AdHoc;
+use rocket::form::{Contextual, Form};
+use rocket::outcome::try_outcome;
+use rocket::request::{FlashMessage, FromRequest, Outcome, Request};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket_db_pools::{sqlx, sqlx::Acquire, sqlx::Row, Connection};
+use rocket_dyn_templates::{context, Template};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Label {
+    pub id: i64,
+    pub owner: i64,
+    pub name: String,
+    pub color: String,
+    // how many projects and tasks carry the label, only counted by get_labels_for_user
+    #[serde(skip_deserializing)]
+    pub uses: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Labels(pub Vec<Label>);
+
+// what a label gets attached to
+pub enum LabelTarget {
+    Project(i64),
+    Task(i64),
+}
+
+fn serialize_label(row: &DbRow) -> Label {
+    Label {
+        id: row.get("id"),
+        owner: row.get("owner"),
+        name: row.get("name"),
+        color: row.get("color"),
+        uses: row.try_get("uses").unwrap_or_default(),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Labels {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<&User>().await);
+        let db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("could not establish db connection");
+        // get all labels the user has created
+        match get_labels_for_user(db, user.id.unwrap()).await {
+            Ok(labels) => Outcome::Success(Labels(labels)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+pub async fn get_labels_for_user(mut db: Connection<Db>, owner: i64) -> Result<Vec<Label>, String> {
+    let result = db::query(
+        "
+        SELECT l.*,
+            (SELECT COUNT(*) FROM project_label pl WHERE pl.label_id = l.id)
+                + (SELECT COUNT(*) FROM task_label tl WHERE tl.label_id = l.id) AS uses
+        FROM label l
+        WHERE l.owner = ?
+        ORDER BY l.name",
+    )
+    .bind(owner)
+    .fetch_all(&mut *db)
+    .await;
+    match result {
+        Ok(rows) => Ok(rows.iter().map(serialize_label).collect()),
+        Err(e) => Err(format!("Failed to get labels: {}", e)),
+    }
+}
+
+// labels of every project `user_id` is a member of, keyed by project id. whoever
+// owns a label, once it is attached every member of the project sees it
+pub async fn get_project_labels(
+    db: &mut DbConnection,
+    user_id: i64,
+) -> Result<Vec<(i64, Label)>, sqlx::Error> {
+    let rows = db::query(
+        "SELECT pl.project_id, l.* FROM project_label pl
+        JOIN label l ON l.id = pl.label_id
+        JOIN project p ON p.id = pl.project_id
+        WHERE p.owner = ? OR (',' || p.participants || ',') LIKE ('%,' || ? || ',%')
+        ORDER BY l.name",
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("project_id"), serialize_label(row)))
+        .collect())
+}
+
+// labels of every task in the project, keyed by task id
+pub async fn get_task_labels(
+    db: &mut DbConnection,
+    proj_id: i64,
+) -> Result<Vec<(i64, Label)>, sqlx::Error> {
+    let rows = db::query(
+        "SELECT tl.task_id, l.* FROM task_label tl
+        JOIN label l ON l.id = tl.label_id
+        JOIN proj_tasks t ON t.id = tl.task_id
+        WHERE t.owner_proj = ?
+        ORDER BY l.name",
+    )
+    .bind(proj_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("task_id"), serialize_label(row)))
+        .collect())
+}
+
+pub async fn add_label(
+    mut db: Connection<Db>,
+    owner: i64,
+    name: &str,
+    color: &str,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query(
+        "INSERT INTO label (owner, name, color) VALUES (?, ?, ?)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(owner)
+    .bind(name)
+    .bind(color)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+pub async fn rename_label(
+    mut db: Connection<Db>,
+    owner: i64,
+    id: i64,
+    name: &str,
+    color: &str,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query("UPDATE label SET name = ?, color = ? WHERE id = ? AND owner = ?")
+        .bind(name)
+        .bind(color)
+        .bind(id)
+        .bind(owner)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+// moves every use of `from` over to `into` and deletes `from`
+pub async fn merge_labels(
+    mut db: Connection<Db>,
+    owner: i64,
+    from: i64,
+    into: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    if from == into {
+        return Ok(None);
+    }
+    let mut tx = (&mut *db).begin().await?;
+
+    let owned: i64 = db::query("SELECT COUNT(*) FROM label WHERE id IN (?, ?) AND owner = ?")
+        .bind(from)
+        .bind(into)
+        .bind(owner)
+        .fetch_one(&mut tx)
+        .await?
+        .get(0);
+    if owned != 2 {
+        return Ok(None);
+    }
+
+    db::query(
+        "INSERT INTO project_label (project_id, label_id)
+        SELECT project_id, ? FROM project_label WHERE label_id = ?
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(into)
+    .bind(from)
+    .execute(&mut tx)
+    .await?;
+    db::query(
+        "INSERT INTO task_label (task_id, label_id)
+        SELECT task_id, ? FROM task_label WHERE label_id = ?
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(into)
+    .bind(from)
+    .execute(&mut tx)
+    .await?;
+    db::query("DELETE FROM project_label WHERE label_id = ?")
+        .bind(from)
+        .execute(&mut tx)
+        .await?;
+    db::query("DELETE FROM task_label WHERE label_id = ?")
+        .bind(from)
+        .execute(&mut tx)
+        .await?;
+    let result = db::query("DELETE FROM label WHERE id = ?")
+        .bind(from)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+pub async fn delete_label_db(
+    mut db: Connection<Db>,
+    owner: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let mut tx = (&mut *db).begin().await?;
+    db::query(
+        "DELETE FROM project_label WHERE label_id = (SELECT id FROM label WHERE id = ? AND owner = ?)",
+    )
+    .bind(id)
+    .bind(owner)
+    .execute(&mut tx)
+    .await?;
+    db::query(
+        "DELETE FROM task_label WHERE label_id = (SELECT id FROM label WHERE id = ? AND owner = ?)",
+    )
+    .bind(id)
+    .bind(owner)
+    .execute(&mut tx)
+    .await?;
+    let result = db::query("DELETE FROM label WHERE id = ? AND owner = ?")
+        .bind(id)
+        .bind(owner)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+// attaches the user's label called `name`, creating it first if needed
+pub async fn attach_label(
+    mut db: Connection<Db>,
+    owner: i64,
+    name: &str,
+    target: LabelTarget,
+) -> Result<Option<()>, sqlx::Error> {
+    let mut tx = (&mut *db).begin().await?;
+
+    db::query(
+        "INSERT INTO label (owner, name) VALUES (?, ?)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(owner)
+    .bind(name)
+    .execute(&mut tx)
+    .await?;
+    let label_id: i64 = db::query("SELECT id FROM label WHERE owner = ? AND name = ?")
+        .bind(owner)
+        .bind(name)
+        .fetch_one(&mut tx)
+        .await?
+        .get(0);
+
+    let result = match target {
+        LabelTarget::Project(project_id) => {
+            db::query(
+                "INSERT INTO project_label (project_id, label_id) VALUES (?, ?)
+                ON CONFLICT DO NOTHING",
+            )
+            .bind(project_id)
+            .bind(label_id)
+            .execute(&mut tx)
+            .await?
+        }
+        LabelTarget::Task(task_id) => {
+            db::query(
+                "INSERT INTO task_label (task_id, label_id) VALUES (?, ?)
+                ON CONFLICT DO NOTHING",
+            )
+            .bind(task_id)
+            .bind(label_id)
+            .execute(&mut tx)
+            .await?
+        }
+    };
+
+    tx.commit().await?;
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+// only the label's owner takes it off again, same as only they attach it
+pub async fn detach_label(
+    mut db: Connection<Db>,
+    owner: i64,
+    label_id: i64,
+    target: LabelTarget,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = match target {
+        LabelTarget::Project(project_id) => {
+            db::query(
+                "DELETE FROM project_label WHERE project_id = ?
+                AND label_id = (SELECT id FROM label WHERE id = ? AND owner = ?)",
+            )
+            .bind(project_id)
+            .bind(label_id)
+            .bind(owner)
+            .execute(&mut *db)
+            .await?
+        }
+        LabelTarget::Task(task_id) => {
+            db::query(
+                "DELETE FROM task_label WHERE task_id = ?
+                AND label_id = (SELECT id FROM label WHERE id = ? AND owner = ?)",
+            )
+            .bind(task_id)
+            .bind(label_id)
+            .bind(owner)
+            .execute(&mut *db)
+            .await?
+        }
+    };
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+pub async fn autocomplete_labels(
+    mut db: Connection<Db>,
+    owner: i64,
+    prefix: &str,
+) -> Result<Vec<Label>, String> {
+    let result = db::query(
+        "SELECT * FROM label WHERE owner = ? AND lower(name) LIKE lower(?) ESCAPE '\\' ORDER BY name LIMIT 10",
+    )
+    .bind(owner)
+    .bind(format!(
+        "{}%",
+        prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    ))
+    .fetch_all(&mut *db)
+    .await;
+    match result {
+        Ok(rows) => Ok(rows.iter().map(serialize_label).collect()),
+        Err(e) => Err(format!("Failed to get labels: {}", e)),
+    }
+}
+
+#[get("/labels")]
+async fn labels_get(user: &User, labels: Labels, flash: Option<FlashMessage<'_>>) -> Template {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    let labels = labels.0;
+    Template::render("labels", context! {user, labels, msg})
+}
+
+#[get("/labels", rank = 2)]
+async fn labels_get_no_auth() -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+// colours end up in a style attribute, so only accept "#rrggbb"
+fn validate_color<'v>(color: &&str) -> rocket::form::Result<'v, ()> {
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Err(rocket::form::Error::validation("invalid colour"))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+struct LabelForm<'v> {
+    #[field(validate = len(1..=32))]
+    name: &'v str,
+    #[field(validate = validate_color(), default = "#888888")]
+    color: &'v str,
+}
+
+#[post("/labels", data = "<form>")]
+async fn add_label_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, LabelForm<'r>>>,
+    user: &User,
+) -> Flash<Redirect> {
+    let labels = Redirect::to(uri!(labels_get));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(labels, "Hmm... That didn't work 🙃"),
+    };
+    match add_label(db, user.id.unwrap(), form_data.name, form_data.color).await {
+        Ok(Some(_)) => Flash::success(labels, "Label added"),
+        Ok(None) => Flash::warning(labels, "That label already exists"),
+        Err(_) => Flash::error(labels, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[post("/labels/<id>/rename", data = "<form>")]
+async fn rename_label_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, LabelForm<'r>>>,
+    user: &User,
+    id: i64,
+) -> Flash<Redirect> {
+    let labels = Redirect::to(uri!(labels_get));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(labels, "Hmm... That didn't work 🙃"),
+    };
+    match rename_label(db, user.id.unwrap(), id, form_data.name, form_data.color).await {
+        Ok(Some(_)) => Flash::success(labels, "Label renamed"),
+        _ => Flash::error(labels, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[derive(FromForm, Debug)]
+struct MergeLabelForm {
+    into: i64,
+}
+
+#[post("/labels/<id>/merge", data = "<form>")]
+async fn merge_label_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, MergeLabelForm>>,
+    user: &User,
+    id: i64,
+) -> Flash<Redirect> {
+    let labels = Redirect::to(uri!(labels_get));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(labels, "Hmm... That didn't work 🙃"),
+    };
+    match merge_labels(db, user.id.unwrap(), id, form_data.into).await {
+        Ok(Some(_)) => Flash::success(labels, "Labels merged"),
+        _ => Flash::error(labels, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/label/<id>")]
+async fn delete_label(db: Connection<Db>, user: &User, id: i64) -> Flash<Redirect> {
+    let labels = Redirect::to(uri!(labels_get));
+    match delete_label_db(db, user.id.unwrap(), id).await {
+        Ok(Some(_)) => Flash::success(labels, "Label deleted"),
+        _ => Flash::error(labels, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/labels/autocomplete?<q>")]
+async fn autocomplete(db: Connection<Db>, user: &User, q: &str) -> Json<Vec<Label>> {
+    let labels = autocomplete_labels(db, user.id.unwrap(), q)
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            vec![]
+        });
+    Json(labels)
+}
+
+#[derive(FromForm, Debug)]
+struct AttachLabelForm<'v> {
+    #[field(validate = len(1..=32))]
+    name: &'v str,
+}
+
+#[post("/project/<proj_id>/label", data = "<form>")]
+async fn attach_project_label<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, AttachLabelForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, None).await {
+        return Flash::error(project, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    let target = LabelTarget::Project(proj_id);
+    match attach_label(db, user.id.unwrap(), form_data.name, target).await {
+        Ok(_) => Flash::success(project, "Label added"),
+        Err(_) => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[post("/project/<proj_id>/task/<task_id>/label", data = "<form>")]
+async fn attach_task_label<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, AttachLabelForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    let target = LabelTarget::Task(task_id);
+    match attach_label(db, user.id.unwrap(), form_data.name, target).await {
+        Ok(_) => Flash::success(project, "Label added"),
+        Err(_) => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/label/<label_id>")]
+async fn detach_project_label(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    label_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, None).await {
+        return Flash::error(project, e.to_string());
+    }
+    let target = LabelTarget::Project(proj_id);
+    match detach_label(db, user.id.unwrap(), label_id, target).await {
+        Ok(Some(_)) => Flash::success(project, "Label removed"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/task/<task_id>/label/<label_id>")]
+async fn detach_task_label(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    label_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    let target = LabelTarget::Task(task_id);
+    match detach_label(db, user.id.unwrap(), label_id, target).await {
+        Ok(Some(_)) => Flash::success(project, "Label removed"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("label stage", |rocket| async {
+        rocket.mount(
+            "/",
+            routes![
+                add_label_post,
+                attach_project_label,
+                attach_task_label,
+                autocomplete,
+                delete_label,
+                detach_project_label,
+                detach_task_label,
+                labels_get,
+                labels_get_no_auth,
+                merge_label_post,
+                rename_label_post,
+            ],
+        )
+    })
+}
@@ -0,0 +1,1205 @@
+#[macro_use]
+extern crate rocket;
+
+pub mod auth;
+pub mod catcher;
+pub mod comment;
+pub mod db;
+pub mod error;
+mod forms;
+pub mod health;
+pub mod job;
+pub mod label;
+pub mod live;
+pub mod mail;
+pub mod mention;
+pub mod metrics;
+pub mod notification;
+pub mod recurrence;
+pub mod reminder;
+pub mod request_id;
+pub mod seed;
+#[cfg(test)]
+mod tests;
+pub mod user;
+pub mod webhook;
+
+use auth::verify_password;
+use comment::Comments;
+use error::{AppError, AppResult};
+use forms::{
+    AddProjectForm, AddTaskForm, ChecklistItemForm, DependencyForm, EditProjectForm, EditTaskForm,
+    EditTaskTimesForm, EstimateForm, LoginForm, MoveTaskForm, ParticipantForm,
+    UserRegistrationForm, WipLimitForm,
+};
+use label::Labels;
+use live::Live;
+use metrics::Metrics;
+use recurrence::{ProjectSeries, RRule};
+use reminder::Reminders;
+use rocket::form::{Contextual, Form};
+use rocket::fs::{relative, FileServer};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::outcome::try_outcome;
+use rocket::request::{FlashMessage, FromRequest, Outcome, Request};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::Json;
+use rocket::{Build, Rocket, State};
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::{context, Template};
+use std::collections::HashMap;
+use user::{
+    add_checklist_item, add_dependency_db, add_participant_db, add_project, add_task,
+    add_time_delta, add_user, complete_task_db, critical_path, delete_checklist_item,
+    delete_dependency_db, delete_project_db, delete_task_db, edit_project, edit_task_db,
+    edit_task_times_db, get_all_projects_and_tasks_for_user, get_all_projects_for_user,
+    get_all_tasks_for_project, get_board_for_project, get_member_projects, get_open_children,
+    get_overdue_tasks_for_user, get_project_by_id, get_task_and_members, get_task_times,
+    get_user_by_email, get_user_by_id, move_task_db, parse_date, remove_participant_db,
+    reopen_task_db, require_member, require_owner, set_task_estimate, set_wip_limit,
+    toggle_checklist_item, user_req_guard, Admin, CompleteTask, CriticalPath, Db, ProjectTasks,
+    Projects, User,
+};
+
+// #[rocket::async_trait]
+// impl<'r> FromRequest<'r> for User {
+//     type Error = std::convert::Infallible;
+//     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+//         if let Some(cookie) = request.cookies().get_private("user_id_in_cookie") {
+//             if let Ok(id) = cookie.value().parse::<i64>() {
+//                 let db = request
+//                     .guard::<Connection<Db>>()
+//                     .await
+//                     .succeeded()
+//                     .expect("coul not establish db connection");
+//                 match user_req_guard(db, id).await {
+//                     Some(user) => {
+//                         return Outcome::Success(user);
+//                     }
+//                     None => return Outcome::Forward(()),
+//                 }
+//             }
+//         }
+//         Outcome::Forward(())
+//     }
+// }
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r User {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user_result = request
+            .local_cache_async(async {
+                if let Some(cookie) = request.cookies().get_private("user_id_in_cookie") {
+                    if let Ok(id) = cookie.value().parse::<i64>() {
+                        let db = request
+                            .guard::<Connection<Db>>()
+                            .await
+                            .succeeded()
+                            .expect("could not establish db connection");
+                        return user_req_guard(db, id).await.ok();
+                    }
+                }
+                None
+            })
+            .await;
+
+        match user_result.as_ref() {
+            Some(user) => Outcome::Success(user),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // This will unconditionally query the database!
+        let user = try_outcome!(request.guard::<&User>().await);
+        if user.admin {
+            {
+                let user = user.clone();
+                Outcome::Success(Admin { user })
+            }
+        } else {
+            Outcome::Forward(())
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Projects {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<&User>().await);
+        let db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("coul not establish db connection");
+        // get all projects for the user
+        let projects = get_all_projects_for_user(db, user.id.unwrap()).await;
+        match projects {
+            Ok(projects) => Outcome::Success(Projects(projects)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ProjectTasks {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // this request guard will get all tasks for a project
+        // project id comes from the url
+        let proj_id = request.param(1).unwrap().unwrap();
+        // an optional `?label=<id>` narrows the list down to tasks carrying that label
+        let label = request
+            .query_value::<i64>("label")
+            .and_then(|label| label.ok());
+
+        let db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("coul not establish db connection");
+        // get all tasks for the project
+        let tasks = get_all_tasks_for_project(db, proj_id).await;
+        match tasks {
+            Ok(mut tasks) => {
+                if let Some(label) = label {
+                    tasks.retain(|task| task.labels.iter().any(|l| l.id == label));
+                }
+                Outcome::Success(ProjectTasks(tasks))
+            }
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CompleteTask {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<&User>().await);
+        let task_id = request.param(4).unwrap().unwrap();
+        // tasks with open subtasks or checklist items need `?confirm=true`
+        let confirmed = request
+            .query_value::<bool>("confirm")
+            .and_then(|confirm| confirm.ok())
+            .unwrap_or(false);
+        let proj_id = request.param(2).unwrap().unwrap();
+        let mut db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("coul not establish db connection");
+        if require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id))
+            .await
+            .is_err()
+        {
+            return Outcome::Forward(());
+        }
+        let live = request
+            .rocket()
+            .state::<Live>()
+            .expect("live updates are not managed");
+        // forwarding lands on complete_task_confirm, which asks for the confirmation
+        match complete_task_db(db, user.id.unwrap(), task_id, confirmed).await {
+            Ok(Some(completed)) => {
+                for event in completed {
+                    live.publish(event);
+                }
+                Outcome::Success(CompleteTask(()))
+            }
+            Ok(None) => Outcome::Forward(()),
+            Err(e) => {
+                error!("Failed to complete task {}: {:?}", task_id, e);
+                Outcome::Forward(())
+            }
+        }
+    }
+}
+
+// fn get_flash_msg(flash: Option<FlashMessage<'_>>) -> String {
+//     flash
+//         .map(|flash| format!("{}: {}", flash.kind(), flash.message()))
+//         .unwrap_or_default()
+// }
+
+// fn get_flash_msg(flash: Option<FlashMessage<'_>>) -> String {
+//     flash
+//         .map(|flash| flash.message().to_string())
+//         .unwrap_or_default()
+// }
+
+fn get_flash_msg(flash: Option<FlashMessage>) -> Result<(String, String), ()> {
+    match flash {
+        Some(flash) => Ok(flash.into_inner()),
+        None => Err(()),
+    }
+}
+
+#[get("/")]
+fn index(user: &User) -> Template {
+    Template::render("index", context! {user})
+}
+
+#[get("/", rank = 2)]
+fn index_no_auth() -> Template {
+    Template::render("index", context! {})
+}
+
+#[get("/login")]
+fn login_get(user: &User) -> Template {
+    Template::render("index", context! {user})
+}
+
+#[get("/login", rank = 2)]
+fn login_get_no_auth() -> Template {
+    Template::render("login", context! {})
+}
+
+#[get("/add-user")]
+fn add_user_get(user: Option<&User>) -> Template {
+    match user {
+        Some(user) => Template::render("index", context! {user}),
+        None => Template::render("add-user", context! {}),
+    }
+}
+
+#[post("/add-user", data = "<form>")]
+async fn add_user_post<'r>(
+    form: Form<Contextual<'r, UserRegistrationForm<'r>>>,
+    mut db: Connection<Db>,
+) -> AppResult<(Status, Template)> {
+    let template = match form.value {
+        Some(ref submission) => {
+            if submission.password == submission.password_check {
+                add_user(
+                    &mut db,
+                    submission.name,
+                    submission.email,
+                    submission.password,
+                )
+                .await?;
+                Template::render("add-user", &form.context)
+            } else {
+                Template::render("add-user", context! {})
+            }
+        }
+        None => Template::render("add-user", &form.context),
+    };
+
+    Ok((form.context.status(), template))
+}
+
+#[post("/login", data = "<form>")]
+async fn login_post<'r>(
+    cookies: &CookieJar<'_>,
+    form: Form<Contextual<'r, LoginForm<'r>>>,
+    db: Connection<Db>,
+    metrics: &State<Metrics>,
+) -> Template {
+    match form.value {
+        Some(ref submission) => {
+            let user = get_user_by_email(db, submission.email).await;
+            match user {
+                Ok(user) => {
+                    let verified = verify_password(submission.password, user.password.as_str());
+                    metrics.login(verified);
+                    if verified {
+                        cookies.add_private(Cookie::new(
+                            "user_id_in_cookie",
+                            user.id.expect("hmm").to_string(),
+                        ));
+                        let mut context = HashMap::new();
+                        context.insert("user", user.0);
+                        Template::render("index", &context)
+                    } else {
+                        Template::render("login", context! {})
+                    }
+                }
+                Err(_) => {
+                    metrics.login(false);
+                    Template::render("login", context! {})
+                }
+            }
+        }
+        None => {
+            metrics.login(false);
+            Template::render("login", context! {})
+        }
+    }
+}
+
+#[get("/logout")]
+fn logout(cookies: &CookieJar<'_>) -> Template {
+    cookies.remove_private(Cookie::named("user_id_in_cookie"));
+    Template::render("index", context! {})
+}
+
+#[get("/user/<id>")]
+async fn user_id(db: Connection<Db>, id: i64, admin: Admin) -> AppResult<Template> {
+    let user = get_user_by_id(db, id).await?;
+    Ok(Template::render(
+        "user-id",
+        context! {
+            user: user.0,
+            admin: admin.user
+        },
+    ))
+}
+
+#[get("/user/<_id>", rank = 2)]
+async fn user_id_no_auth(_id: i64) -> Redirect {
+    Redirect::to(uri!("/"))
+}
+
+#[get("/profile?<label>")]
+async fn profile(
+    db: Connection<Db>,
+    user: &User,
+    labels: Labels,
+    label: Option<i64>,
+    flash: Option<FlashMessage<'_>>,
+) -> AppResult<Template> {
+    let msg = get_flash_msg(flash);
+
+    let proj_w_tasks = get_all_projects_and_tasks_for_user(db, user.id.unwrap(), label).await?;
+    let labels = labels.0;
+
+    Ok(match msg {
+        Ok(msg) => {
+            let context = context! {user, proj_w_tasks, labels, label, msg};
+            Template::render("profile", context)
+        }
+        Err(_) => Template::render("profile", context! {user, proj_w_tasks, labels, label}),
+    })
+}
+
+#[get("/profile", rank = 2)]
+async fn profile_no_auth() -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[get("/project/<id>")]
+#[allow(clippy::too_many_arguments)]
+async fn project_id(
+    mut db: Connection<Db>,
+    id: i64,
+    user: &User,
+    tasks: ProjectTasks,
+    comments: Comments,
+    series: ProjectSeries,
+    reminders: Reminders,
+    flash: Option<FlashMessage<'_>>,
+) -> AppResult<Template> {
+    require_member(&mut db, user.id.unwrap(), id, None).await?;
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    let project = get_project_by_id(db, id).await?;
+    let comments = comments.0;
+    let series = series.0;
+    let reminders = reminders.0;
+    let context = context! {project, user, tasks, comments, series, reminders, msg};
+    Ok(Template::render("project-id", context))
+}
+
+#[get("/edit/project/<id>")]
+async fn edit_project_get(mut db: Connection<Db>, user: &User, id: i64) -> AppResult<Template> {
+    require_owner(&mut db, user.id.unwrap(), id).await?;
+    let project = get_project_by_id(db, id).await?;
+    let context = context! {user, project};
+    Ok(Template::render("project-edit", context))
+}
+
+#[get("/edit/project/<_id>", rank = 2)]
+async fn edit_project_get_no_auth(_id: i64) -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[post("/edit/project/<id>", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn edit_project_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, EditProjectForm<'r>>>,
+    user: Option<&User>,
+    live: &State<Live>,
+    id: i64,
+) -> AppResult<Redirect> {
+    match user {
+        Some(user) => {
+            require_owner(&mut db, user.id.unwrap(), id).await?;
+            let form_data = form
+                .value
+                .as_ref()
+                .ok_or_else(|| AppError::Validation("Invalid project details".to_string()))?;
+            let event = edit_project(db, id, form_data.name, form_data.end_date).await?;
+            live.publish(event);
+            Ok(Redirect::to(uri!(project_id(id))))
+        }
+        None => Ok(Redirect::to(uri!("/login"))),
+    }
+}
+
+#[get("/delete/project/<id>")]
+#[allow(clippy::redundant_locals)]
+async fn delete_project(
+    mut db: Connection<Db>,
+    user: &User,
+    live: &State<Live>,
+    id: i64,
+) -> Flash<Redirect> {
+    if let Err(e) = require_owner(&mut db, user.id.unwrap(), id).await {
+        return Flash::error(Redirect::to(uri!(project_id(id))), e.to_string());
+    }
+    let result = delete_project_db(db, id).await;
+    match result {
+        Ok(event) => {
+            live.publish(event);
+            Flash::success(Redirect::to(uri!("/profile")), "Project deleted")
+        }
+        Err(_) => Flash::error(Redirect::to(uri!("/profile")), "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[post("/project/<id>/participant", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn add_participant_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, ParticipantForm<'r>>>,
+    projects: Projects,
+    id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(id)));
+    if !projects.0.iter().any(|p| p.id == Some(id)) {
+        return Flash::error(project, "Only the project owner can add participants");
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    match add_participant_db(db, id, form_data.email.trim()).await {
+        Ok(_) => Flash::success(project, "Participant added"),
+        Err(AppError::Database(_)) => Flash::error(project, "Hmm... That didn't work 🙃"),
+        Err(e) => Flash::warning(project, e.to_string()),
+    }
+}
+
+#[get("/delete/project/<proj_id>/participant/<user_id>")]
+async fn remove_participant(
+    db: Connection<Db>,
+    projects: Projects,
+    proj_id: i64,
+    user_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if !projects.0.iter().any(|p| p.id == Some(proj_id)) {
+        return Flash::error(project, "Only the project owner can remove participants");
+    }
+    match remove_participant_db(db, proj_id, user_id).await {
+        Ok(_) => Flash::success(project, "Participant removed"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/task/<task_id>")]
+async fn delete_task(
+    mut db: Connection<Db>,
+    user: &User,
+    live: &State<Live>,
+    task_id: i64,
+    proj_id: i64,
+) -> Flash<Redirect> {
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(Redirect::to(uri!(project_id(proj_id))), e.to_string());
+    }
+    let result = delete_task_db(db, task_id).await;
+    match result {
+        Ok(deleted) => {
+            for event in deleted {
+                live.publish(event);
+            }
+            Flash::success(Redirect::to(uri!(project_id(proj_id))), "Task deleted")
+        }
+        Err(_) => Flash::error(
+            Redirect::to(uri!(project_id(proj_id))),
+            "Hmm... That didn't work 🙃",
+        ),
+    }
+}
+
+#[get("/complete/project/<proj_id>/task/<task_id>")]
+async fn complete_task(
+    db: Connection<Db>,
+    task_id: i64,
+    proj_id: i64,
+    _complete: CompleteTask,
+) -> Flash<Redirect> {
+    let time_delta = add_time_delta(db, task_id).await;
+    match time_delta {
+        Ok(_) => Flash::success(Redirect::to(uri!(project_id(proj_id))), "Task completed"),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(project_id(proj_id))),
+            "Hmm... That didn't work 🙃",
+        ),
+    }
+}
+
+#[get("/complete/project/<proj_id>/task/<task_id>", rank = 2)]
+async fn complete_task_confirm(
+    mut db: Connection<Db>,
+    user: &User,
+    task_id: i64,
+    proj_id: i64,
+) -> Result<Template, Flash<Redirect>> {
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Err(Flash::error(Redirect::to(uri!("/profile")), e.to_string()));
+    }
+    let children = match get_open_children(&mut db, task_id).await {
+        Ok(children) => get_project_by_id(db, proj_id)
+            .await
+            .map(|project| (project, children)),
+        Err(e) => Err(e),
+    };
+    match children {
+        Ok((project, (subtasks, checklist))) if !subtasks.is_empty() || !checklist.is_empty() => {
+            let context = context! {user, project, task_id, subtasks, checklist};
+            Ok(Template::render("task-complete", context))
+        }
+        _ => Err(Flash::error(
+            Redirect::to(uri!(project_id(proj_id))),
+            "Hmm... That didn't work 🙃",
+        )),
+    }
+}
+
+#[post("/project/<proj_id>/task/<task_id>/checklist", data = "<form>")]
+async fn add_checklist_item_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, ChecklistItemForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    match add_checklist_item(db, proj_id, task_id, form_data.text).await {
+        Ok(_) => Flash::success(project, "Checklist item added"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/toggle/project/<proj_id>/task/<task_id>/checklist/<item_id>")]
+async fn toggle_checklist_item_get(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    item_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match toggle_checklist_item(db, proj_id, task_id, item_id).await {
+        Ok(_) => Flash::success(project, "Checklist item updated"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/task/<task_id>/checklist/<item_id>")]
+async fn delete_checklist_item_get(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    item_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match delete_checklist_item(db, proj_id, task_id, item_id).await {
+        Ok(_) => Flash::success(project, "Checklist item deleted"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[post("/project/<proj_id>/task/<task_id>/dependency", data = "<form>")]
+async fn add_dependency_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, DependencyForm>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    match add_dependency_db(db, proj_id, task_id, form_data.blocked_by).await {
+        Ok(_) => Flash::success(project, "Dependency added"),
+        Err(e) => Flash::warning(project, e.to_string()),
+    }
+}
+
+#[get("/delete/project/<proj_id>/task/<task_id>/dependency/<blocked_by>")]
+async fn delete_dependency(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    blocked_by: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match delete_dependency_db(db, proj_id, task_id, blocked_by).await {
+        Ok(_) => Flash::success(project, "Dependency removed"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[post("/project/<proj_id>/task/<task_id>/estimate", data = "<form>")]
+async fn estimate_task_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, EstimateForm>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    let estimate = form_data
+        .hours
+        .map(|hours| (hours.max(0.0) * 3600.0) as i64);
+    match set_task_estimate(db, proj_id, task_id, estimate).await {
+        Ok(_) => Flash::success(project, "Estimate updated"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/project/<id>/critical-path")]
+async fn project_critical_path(
+    mut db: Connection<Db>,
+    user: &User,
+    id: i64,
+    tasks: ProjectTasks,
+) -> Option<Json<CriticalPath>> {
+    require_member(&mut db, user.id.unwrap(), id, None)
+        .await
+        .ok()?;
+    Some(Json(critical_path(&tasks.0)))
+}
+
+#[get("/add-project")]
+fn add_project_get(user: Option<&User>) -> Result<Redirect, Box<Template>> {
+    match user {
+        Some(user) => {
+            let context = context! {user};
+            Err(Box::new(Template::render("add-project", context)))
+        }
+        None => Ok(Redirect::to(uri!("/login"))),
+    }
+}
+
+#[post("/add-project", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn add_project_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, AddProjectForm<'r>>>,
+    user: Option<&User>,
+    live: &State<Live>,
+) -> AppResult<Redirect> {
+    match user {
+        Some(user) => {
+            let form_data = form
+                .value
+                .as_ref()
+                .ok_or_else(|| AppError::Validation("A project needs a name".to_string()))?;
+            let event = add_project(db, form_data.name, user.id.unwrap()).await?;
+            let id = event.project_id;
+            live.publish(event);
+            Ok(Redirect::to(uri!(project_id(id))))
+        }
+        None => Ok(Redirect::to(uri!("/login"))),
+    }
+}
+
+#[get("/project/<id>/add-task?<parent>")]
+async fn add_task_get(
+    mut db: Connection<Db>,
+    user: &User,
+    id: i64,
+    parent: Option<i64>,
+) -> AppResult<Template> {
+    require_member(&mut db, user.id.unwrap(), id, None).await?;
+    let project = get_project_by_id(db, id).await?;
+    let context = context! {user, project, parent};
+    Ok(Template::render("add-task", context))
+}
+
+#[get("/project/<_id>/add-task", rank = 2)]
+async fn add_task_get_no_auth(_id: i64) -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[post("/project/<id>/add-task", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn add_task_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, AddTaskForm<'r>>>,
+    user: Option<&User>,
+    live: &State<Live>,
+    id: i64,
+) -> Result<Flash<Redirect>, Redirect> {
+    match user {
+        Some(user) => {
+            if let Err(e) = require_member(&mut db, user.id.unwrap(), id, None).await {
+                return Ok(Flash::error(Redirect::to(uri!("/profile")), e.to_string()));
+            }
+            let form_data = match form.value {
+                Some(ref form_data) => form_data,
+                None => {
+                    return Ok(Flash::error(
+                        Redirect::to(uri!(add_task_get(id, _))),
+                        "Hmm... That didn't work 🙃",
+                    ))
+                }
+            };
+            let due_date = match form_data.due_date {
+                "" => None,
+                due_date => match parse_date(due_date) {
+                    Some(due_date) => Some(due_date),
+                    None => {
+                        return Ok(Flash::error(
+                            Redirect::to(uri!(add_task_get(id, _))),
+                            "Invalid due date",
+                        ))
+                    }
+                },
+            };
+            let rrule = match form_data.rrule.map(str::trim) {
+                None | Some("") => None,
+                Some(rrule) => match RRule::parse(rrule) {
+                    Ok(_) => Some(rrule),
+                    Err(e) => {
+                        return Ok(Flash::error(
+                            Redirect::to(uri!(add_task_get(id, _))),
+                            format!("Invalid recurrence: {}", e),
+                        ))
+                    }
+                },
+            };
+            let result = add_task(
+                db,
+                user.id.unwrap(),
+                form_data.description,
+                id,
+                form_data.priority,
+                due_date,
+                form_data.parent_id,
+                rrule,
+            )
+            .await;
+            match result {
+                Ok(event) => {
+                    live.publish(event);
+                    Ok(Flash::success(
+                        Redirect::to(uri!(project_id(id))),
+                        "Task added",
+                    ))
+                }
+                Err(e) => Ok(Flash::error(
+                    Redirect::to(uri!(add_task_get(id, _))),
+                    e.to_string(),
+                )),
+            }
+        }
+        None => Err(Redirect::to(uri!("/login"))),
+    }
+}
+
+#[get("/edit/project/<proj_id>/task/<task_id>")]
+async fn edit_task_get(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, Redirect> {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    if require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id))
+        .await
+        .is_err()
+    {
+        return Err(Redirect::to(uri!("/profile")));
+    }
+    // the projects a task can be moved to
+    let projects = get_member_projects(&mut db, user.id.unwrap())
+        .await
+        .unwrap_or_default();
+    let project = projects.iter().find(|p| p.id == Some(proj_id));
+    match get_task_and_members(db, task_id).await {
+        Ok((task, members)) => {
+            let projects = &projects;
+            let context = context! {user, project, projects, task, members, msg};
+            Ok(Template::render("task-edit", context))
+        }
+        _ => Err(Redirect::to(uri!(project_id(proj_id)))),
+    }
+}
+
+#[get("/edit/project/<_proj_id>/task/<_task_id>", rank = 2)]
+async fn edit_task_get_no_auth(_proj_id: i64, _task_id: i64) -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[post("/edit/project/<proj_id>/task/<task_id>", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn edit_task_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, EditTaskForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Result<Flash<Redirect>, (Status, Template)> {
+    let edit = Redirect::to(uri!(edit_task_get(proj_id, task_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Ok(Flash::error(edit, e.to_string()));
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => {
+            let projects = get_member_projects(&mut db, user.id.unwrap())
+                .await
+                .unwrap_or_default();
+            let project = projects.iter().find(|p| p.id == Some(proj_id));
+            // re-render the form with the validation errors from the context
+            return match get_task_and_members(db, task_id).await {
+                Ok((task, members)) => {
+                    let projects = &projects;
+                    let context = context! {
+                        user,
+                        project,
+                        projects,
+                        task,
+                        members,
+                        form: &form.context,
+                    };
+                    Err((
+                        form.context.status(),
+                        Template::render("task-edit", context),
+                    ))
+                }
+                Err(_) => Ok(Flash::error(edit, "Hmm... That didn't work 🙃")),
+            };
+        }
+    };
+    let due_date = parse_date(form_data.due_date);
+    let result = edit_task_db(
+        db,
+        user.id.unwrap(),
+        task_id,
+        form_data.description,
+        form_data.priority,
+        due_date,
+        form_data.assignee,
+        form_data.owner_proj,
+    )
+    .await;
+    match result {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(project_id(form_data.owner_proj))),
+            "Task edited",
+        )),
+        Err(e) => Ok(Flash::error(edit, e.to_string())),
+    }
+}
+
+#[get("/reopen/project/<proj_id>/task/<task_id>")]
+async fn reopen_task(
+    mut db: Connection<Db>,
+    user: &User,
+    task_id: i64,
+    proj_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match reopen_task_db(db, proj_id, task_id).await {
+        Ok(_) => Flash::success(project, "Task reopened"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/edit/project/<proj_id>/task/<task_id>/times")]
+async fn edit_task_times_get(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, Redirect> {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    if require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id))
+        .await
+        .is_err()
+    {
+        return Err(Redirect::to(uri!("/profile")));
+    }
+    let times = match get_task_times(&mut db, task_id).await {
+        Ok(times) => get_project_by_id(db, proj_id)
+            .await
+            .map(|project| (project, times)),
+        Err(e) => Err(e),
+    };
+    match times {
+        Ok((project, times)) => {
+            let context = context! {user, project, times, msg};
+            Ok(Template::render("task-times", context))
+        }
+        _ => Err(Redirect::to(uri!(project_id(proj_id)))),
+    }
+}
+
+#[post("/edit/project/<proj_id>/task/<task_id>/times", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn edit_task_times_post<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, EditTaskTimesForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let edit = Redirect::to(uri!(edit_task_times_get(proj_id, task_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(edit, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => {
+            let errors = form
+                .context
+                .errors()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Flash::error(edit, errors);
+        }
+    };
+    let task_start_date = parse_date(form_data.task_start_date).unwrap_or_default();
+    let task_end_date = parse_date(form_data.task_end_date).unwrap_or_default();
+    match edit_task_times_db(db, proj_id, task_id, &task_start_date, &task_end_date).await {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(project_id(proj_id))),
+            "Task times corrected",
+        ),
+        _ => Flash::error(edit, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/overdue?<label>")]
+async fn overdue(db: Connection<Db>, user: &User, label: Option<i64>) -> AppResult<Template> {
+    let tasks = get_overdue_tasks_for_user(db, user.id.unwrap(), label).await?;
+    Ok(Template::render("overdue", context! {user, tasks}))
+}
+
+#[get("/overdue", rank = 2)]
+async fn overdue_no_auth() -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[get("/project/<id>/board")]
+#[allow(clippy::redundant_locals)]
+async fn project_board(
+    mut db: Connection<Db>,
+    id: i64,
+    user: &User,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, Redirect> {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    if require_member(&mut db, user.id.unwrap(), id, None)
+        .await
+        .is_err()
+    {
+        return Err(Redirect::to(uri!("/profile")));
+    }
+    let board = match get_board_for_project(&mut db, id).await {
+        Ok(columns) => get_project_by_id(db, id)
+            .await
+            .map(|project| (project, columns)),
+        Err(e) => Err(e),
+    };
+    match board {
+        Ok((project, columns)) => {
+            let context = context! {project, user, columns, msg};
+            Ok(Template::render("project-board", context))
+        }
+        Err(e) => {
+            error!("Failed to get board: {:?}", e);
+            Err(Redirect::to(uri!(project_id(id))))
+        }
+    }
+}
+
+#[get("/project/<_id>/board", rank = 2)]
+async fn project_board_no_auth(_id: i64) -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[post("/project/<proj_id>/task/<task_id>/move", data = "<form>")]
+async fn move_task<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, MoveTaskForm<'r>>>,
+    user: &User,
+    live: &State<Live>,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let board = Redirect::to(uri!(project_board(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(board, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(board, "Hmm... That didn't work 🙃"),
+    };
+    let result = move_task_db(
+        db,
+        user.id.unwrap(),
+        proj_id,
+        task_id,
+        form_data.status,
+        form_data.position,
+    )
+    .await;
+    match result {
+        Ok(completed) => {
+            if let Some(event) = completed {
+                live.publish(event);
+            }
+            Flash::success(board, "Task moved")
+        }
+        Err(e) => Flash::warning(board, e.to_string()),
+    }
+}
+
+#[post("/project/<id>/board/wip", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn board_wip_limit<'r>(
+    mut db: Connection<Db>,
+    form: Form<Contextual<'r, WipLimitForm<'r>>>,
+    user: &User,
+    id: i64,
+) -> Flash<Redirect> {
+    let board = Redirect::to(uri!(project_board(id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), id, None).await {
+        return Flash::error(board, e.to_string());
+    }
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(board, "Hmm... That didn't work 🙃"),
+    };
+    match set_wip_limit(db, id, form_data.status, form_data.wip_limit).await {
+        Ok(_) => Flash::success(board, "WIP limit updated"),
+        _ => Flash::error(board, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn rocket() -> Rocket<Build> {
+    // rrs-admin loads its configuration the same way
+    dotenvy::dotenv().ok();
+    rocket::build()
+        .attach(user::stage())
+        .attach(job::stage())
+        .attach(comment::stage())
+        .attach(notification::stage())
+        .attach(recurrence::stage())
+        .attach(reminder::stage())
+        .attach(live::stage())
+        .attach(webhook::stage())
+        .attach(mail::stage())
+        .attach(label::stage())
+        .attach(catcher::stage())
+        .attach(request_id::RequestLog)
+        .attach(metrics::stage())
+        .attach(health::stage())
+        .mount(
+            "/",
+            routes![
+                add_project_get,
+                add_project_post,
+                add_task_get,
+                add_task_get_no_auth,
+                add_participant_post,
+                add_task_post,
+                add_user_get,
+                add_checklist_item_post,
+                add_dependency_post,
+                add_user_post,
+                board_wip_limit,
+                complete_task,
+                complete_task_confirm,
+                delete_checklist_item_get,
+                delete_dependency,
+                delete_project,
+                delete_task,
+                edit_project_get,
+                edit_project_get_no_auth,
+                edit_project_post,
+                edit_task_get,
+                edit_task_get_no_auth,
+                edit_task_post,
+                edit_task_times_get,
+                edit_task_times_post,
+                estimate_task_post,
+                index,
+                index_no_auth,
+                login_get,
+                login_get_no_auth,
+                login_post,
+                logout,
+                move_task,
+                overdue,
+                overdue_no_auth,
+                profile,
+                profile_no_auth,
+                project_board,
+                project_board_no_auth,
+                project_critical_path,
+                project_id,
+                remove_participant,
+                reopen_task,
+                toggle_checklist_item_get,
+                user_id,
+                user_id_no_auth,
+            ],
+        )
+        .mount("/", FileServer::from(relative!("static/")))
+}
@@ -0,0 +1,86 @@
+use crate::user::{get_project_members, Db, User};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Value;
+use rocket::serde::Serialize;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
+use rocket::{Shutdown, State};
+use rocket_db_pools::Connection;
+
+// something that happened to a project, e.g. "task.created" or "project.updated"
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ProjectEvent {
+    pub project_id: i64,
+    pub event: String,
+    pub task_id: Option<i64>,
+    // whatever the page needs to show the change without reloading
+    pub data: Value,
+}
+
+impl ProjectEvent {
+    pub fn new(project_id: i64, event: &str, task_id: Option<i64>, data: Value) -> Self {
+        ProjectEvent {
+            project_id,
+            event: event.to_string(),
+            task_id,
+            data,
+        }
+    }
+}
+
+// the in-process channel every open project page listens on
+#[derive(Clone)]
+pub struct Live(pub Sender<ProjectEvent>);
+
+impl Live {
+    // handlers publish the events the data layer returns once its transaction is committed
+    pub fn publish(&self, event: ProjectEvent) {
+        // sending only fails when nobody is listening, which is fine
+        let _ = self.0.send(event);
+    }
+}
+
+#[get("/project/<id>/events")]
+async fn project_events(
+    mut db: Connection<Db>,
+    user: &User,
+    live: &State<Live>,
+    id: i64,
+    mut end: Shutdown,
+) -> Result<EventStream![], Status> {
+    // only the owner and participants get to follow along
+    let members = get_project_members(&mut db, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if !members.iter().any(|member| member.id == user.id) {
+        return Err(Status::Forbidden);
+    }
+
+    let mut rx = live.0.subscribe();
+    Ok(EventStream! {
+        loop {
+            let event = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+            if event.project_id == id {
+                yield Event::json(&event).event(event.event.clone());
+            }
+        }
+    })
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("live stage", |rocket| async {
+        rocket
+            .manage(Live(channel::<ProjectEvent>(1024).0))
+            .mount("/", routes![project_events])
+    })
+}
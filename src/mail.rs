@@ -0,0 +1,502 @@
+// Outgoing email. The transport is picked with the `mail` config key, e.g. in
+// Rocket.toml or as ROCKET_MAIL='{transport="smtp",smtp_host="mail.example.com"}':
+//   transport = "smtp" | "file" | "memory"   (default "file")
+//   from = "rrs <noreply@localhost>"
+//   base_url = "http://localhost:8000"         (links in mails point here)
+//   dir = "mail"                               (file transport, one .eml per mail)
+//   smtp_host, smtp_port, smtp_username, smtp_password
+// Bodies are rendered from templates/email by the Tera instance the template
+// fairing loads, which mail::stage attaches. A bad `mail` config stops the launch.
+
+// rocket's FromForm derive still emits the removed private_in_public lint
+#![allow(renamed_and_removed_lints)]
+
+use crate::db::{self, DbPool};
+use crate::get_flash_msg;
+use crate::reminder::REMINDER_CHANNELS;
+use crate::user::{Db, User};
+use chrono::{Duration, Utc};
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::fairing::AdHoc;
+use rocket::form::{Contextual, Form};
+use rocket::request::FlashMessage;
+use rocket::response::{Flash, Redirect};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::sync::Mutex;
+use rocket_db_pools::{sqlx, sqlx::Row, Connection};
+use rocket_dyn_templates::{context, Template};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tera::Tera;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub html: String,
+}
+
+#[rocket::async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String>;
+}
+
+fn build_message(from: &str, email: &Email) -> Result<Message, String> {
+    let from: Mailbox = from.parse().map_err(|e| format!("bad sender: {}", e))?;
+    let to: Mailbox = email
+        .to
+        .parse()
+        .map_err(|e| format!("bad recipient: {}", e))?;
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_HTML)
+        .body(email.html.clone())
+        .map_err(|e| e.to_string())
+}
+
+pub struct SmtpTransport(AsyncSmtpTransport<Tokio1Executor>);
+
+#[rocket::async_trait]
+impl Transport for SmtpTransport {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        let message = build_message(from, email)?;
+        self.0
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// drops every mail as an .eml file into a directory, handy in development
+pub struct FileTransport(PathBuf);
+
+#[rocket::async_trait]
+impl Transport for FileTransport {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        let message = build_message(from, email)?;
+        rocket::tokio::fs::create_dir_all(&self.0)
+            .await
+            .map_err(|e| e.to_string())?;
+        let name = format!("{}.eml", Utc::now().format("%Y%m%d-%H%M%S%.f"));
+        rocket::tokio::fs::write(self.0.join(name), message.formatted())
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+// keeps mails in memory so tests can look at what was sent
+#[derive(Default)]
+pub struct MemoryTransport(pub Mutex<Vec<Email>>);
+
+#[rocket::async_trait]
+impl Transport for MemoryTransport {
+    async fn send(&self, _from: &str, email: &Email) -> Result<(), String> {
+        self.0.lock().await.push(email.clone());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MailConfig {
+    #[serde(default = "default_transport")]
+    transport: String,
+    #[serde(default = "default_from")]
+    from: String,
+    #[serde(default = "default_dir")]
+    dir: String,
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    #[serde(default)]
+    smtp_host: String,
+    smtp_port: Option<u16>,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+}
+
+fn default_transport() -> String {
+    "file".to_string()
+}
+
+fn default_from() -> String {
+    "rust-rocket-sqlx <noreply@localhost>".to_string()
+}
+
+fn default_dir() -> String {
+    "mail".to_string()
+}
+
+fn default_base_url() -> String {
+    "http://localhost:8000".to_string()
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: default_transport(),
+            from: default_from(),
+            dir: default_dir(),
+            base_url: default_base_url(),
+            smtp_host: String::new(),
+            smtp_port: None,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    pub transport: Arc<dyn Transport>,
+    pub from: String,
+    pub base_url: String,
+    // filled in, and refreshed on reloads, by the template fairing
+    templates: Arc<RwLock<Tera>>,
+}
+
+impl Mailer {
+    pub fn new(transport: Arc<dyn Transport>, from: &str, base_url: &str) -> Mailer {
+        Mailer {
+            transport,
+            from: from.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            templates: Arc::default(),
+        }
+    }
+
+    fn from_config(config: &MailConfig) -> Result<Mailer, String> {
+        let transport: Arc<dyn Transport> = match config.transport.as_str() {
+            "smtp" => {
+                let mut smtp = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
+                    .map_err(|e| e.to_string())?;
+                if let Some(port) = config.smtp_port {
+                    smtp = smtp.port(port);
+                }
+                if let (Some(username), Some(password)) =
+                    (&config.smtp_username, &config.smtp_password)
+                {
+                    smtp = smtp.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                Arc::new(SmtpTransport(smtp.build()))
+            }
+            "file" => Arc::new(FileTransport(PathBuf::from(&config.dir))),
+            "memory" => Arc::new(MemoryTransport::default()),
+            other => return Err(format!("unknown mail transport: {}", other)),
+        };
+        Ok(Mailer::new(transport, &config.from, &config.base_url))
+    }
+
+    // renders templates/email/<template>.html.tera, which also gets `base_url`, and sends it
+    pub async fn send(
+        &self,
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &tera::Context,
+    ) -> Result<(), String> {
+        let mut context = context.clone();
+        context.insert("base_url", &self.base_url);
+        let html = self
+            .templates
+            .read()
+            .map_err(|e| e.to_string())?
+            .render(&format!("email/{}", template), &context)
+            .map_err(|e| format!("Failed to render {}: {}", template, e))?;
+        let email = Email {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            html,
+        };
+        self.transport.send(&self.from, &email).await
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Preferences {
+    // mirror in-app notifications to email
+    pub email_notifications: bool,
+    pub email_digest: bool,
+    // how task reminders reach the user, one of REMINDER_CHANNELS
+    pub reminder_channel: String,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            email_notifications: false,
+            email_digest: true,
+            reminder_channel: "app".to_string(),
+        }
+    }
+}
+
+pub async fn get_preferences(mut db: Connection<Db>, user_id: i64) -> Result<Preferences, String> {
+    let result = db::query("SELECT * FROM notification_preference WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *db)
+        .await;
+    match result {
+        Ok(Some(row)) => Ok(Preferences {
+            email_notifications: row.get("email_notifications"),
+            email_digest: row.get("email_digest"),
+            reminder_channel: row.get("reminder_channel"),
+        }),
+        Ok(None) => Ok(Preferences::default()),
+        Err(e) => Err(format!("Failed to get preferences: {}", e)),
+    }
+}
+
+pub async fn set_preferences(
+    mut db: Connection<Db>,
+    user_id: i64,
+    preferences: &Preferences,
+) -> Result<(), sqlx::Error> {
+    db::query(
+        "INSERT INTO notification_preference
+            (user_id, email_notifications, email_digest, reminder_channel, updated)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE
+        SET email_notifications = excluded.email_notifications,
+            email_digest = excluded.email_digest,
+            reminder_channel = excluded.reminder_channel,
+            updated = excluded.updated",
+    )
+    .bind(user_id)
+    .bind(preferences.email_notifications)
+    .bind(preferences.email_digest)
+    .bind(&preferences.reminder_channel)
+    .bind(db::now())
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+// emails notifications that came in since the user asked for them. this is best
+// effort: a notification is only tried once, it stays in the notification center
+pub async fn send_notification_emails(pool: &DbPool, mailer: &Mailer) -> Result<(), String> {
+    let fail = |e: sqlx::Error| format!("Failed to get notifications to email: {}", e);
+
+    let rows = db::query(
+        "SELECT n.id, n.message, n.link, u.email, u.name FROM notification n
+        JOIN \"user\" u ON u.id = n.user_id
+        JOIN notification_preference p ON p.user_id = n.user_id
+        WHERE NOT n.emailed AND p.email_notifications AND n.created >= p.updated
+        ORDER BY n.id
+        LIMIT 50",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(fail)?;
+
+    for row in rows {
+        let mut context = tera::Context::new();
+        context.insert("name", &row.get::<String, _>("name"));
+        context.insert("message", &row.get::<String, _>("message"));
+        context.insert("link", &row.get::<String, _>("link"));
+        let message: String = row.get("message");
+        let email: String = row.get("email");
+        if let Err(e) = mailer
+            .send(&email, &message, "notification", &context)
+            .await
+        {
+            error!("Failed to email notification to {}: {}", email, e);
+        }
+        db::query("UPDATE notification SET emailed = TRUE WHERE id = ?")
+            .bind(row.get::<i64, _>("id"))
+            .execute(pool)
+            .await
+            .map_err(fail)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DigestProject {
+    pub name: String,
+    pub completed: Vec<String>,
+    pub overdue: Vec<String>,
+    // seconds spent on the tasks completed since the last digest
+    pub time_tracked: i64,
+}
+
+// what happened over the last day in the projects the user owns or participates in
+pub async fn get_digest(pool: &DbPool, user_id: i64) -> Result<Vec<DigestProject>, sqlx::Error> {
+    let since = db::timestamp(Utc::now() - Duration::days(1));
+    let rows = db::query(&format!(
+        "SELECT p.name, t.description, t.time_delta,
+            t.task_end_date <> '' AND t.task_end_date >= ? AS completed,
+            t.task_end_date = '' AND t.due_date IS NOT NULL AND t.due_date < {} AS overdue
+        FROM project p
+        JOIN proj_tasks t ON t.owner_proj = p.id
+        WHERE p.owner = ? OR (',' || p.participants || ',') LIKE ('%,' || ? || ',%')
+        ORDER BY p.name, t.id",
+        db::NOW
+    ))
+    .bind(since)
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut projects: Vec<DigestProject> = vec![];
+    for row in rows {
+        let completed: bool = row.get("completed");
+        let overdue: bool = row.get("overdue");
+        if !completed && !overdue {
+            continue;
+        }
+        let name: String = row.get("name");
+        if projects.last().is_none_or(|p| p.name != name) {
+            projects.push(DigestProject {
+                name,
+                completed: vec![],
+                overdue: vec![],
+                time_tracked: 0,
+            });
+        }
+        let project = projects.last_mut().unwrap();
+        if completed {
+            project.completed.push(row.get("description"));
+            project.time_tracked += row.get::<i64, _>("time_delta");
+        } else {
+            project.overdue.push(row.get("description"));
+        }
+    }
+    Ok(projects)
+}
+
+// sends the daily digest to everyone who didn't opt out, skipping quiet days.
+// the mail.digest job runs it every morning
+pub async fn send_digests(pool: &DbPool, mailer: &Mailer) -> Result<(), String> {
+    let users = db::query(
+        "SELECT u.id, u.email, u.name FROM \"user\" u
+        LEFT JOIN notification_preference p ON p.user_id = u.id
+        WHERE p.email_digest IS NULL OR p.email_digest",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get digest recipients: {}", e))?;
+
+    for user in users {
+        let projects = get_digest(pool, user.get("id"))
+            .await
+            .map_err(|e| format!("Failed to build digest: {}", e))?;
+        if projects.is_empty() {
+            continue;
+        }
+        let mut context = tera::Context::new();
+        context.insert("name", &user.get::<String, _>("name"));
+        context.insert("projects", &projects);
+        let email: String = user.get("email");
+        if let Err(e) = mailer
+            .send(&email, "Your daily digest", "digest", &context)
+            .await
+        {
+            error!("Failed to send digest to {}: {}", email, e);
+        }
+    }
+    Ok(())
+}
+
+#[get("/preferences")]
+async fn preferences_get(
+    db: Connection<Db>,
+    user: &User,
+    flash: Option<FlashMessage<'_>>,
+) -> Template {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    let preferences = get_preferences(db, user.id.unwrap())
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            Preferences::default()
+        });
+    let channels = REMINDER_CHANNELS;
+    Template::render("preferences", context! {user, preferences, channels, msg})
+}
+
+#[get("/preferences", rank = 2)]
+async fn preferences_get_no_auth() -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+fn validate_channel<'v>(channel: &&str) -> rocket::form::Result<'v, ()> {
+    if !REMINDER_CHANNELS.contains(channel) {
+        Err(rocket::form::Error::validation("unknown reminder channel"))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+struct PreferencesForm<'v> {
+    email_notifications: bool,
+    email_digest: bool,
+    #[field(validate = validate_channel())]
+    reminder_channel: &'v str,
+}
+
+#[post("/preferences", data = "<form>")]
+async fn preferences_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, PreferencesForm<'r>>>,
+    user: &User,
+) -> Flash<Redirect> {
+    let preferences = Redirect::to(uri!(preferences_get));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(preferences, "Hmm... That didn't work 🙃"),
+    };
+    let new = Preferences {
+        email_notifications: form_data.email_notifications,
+        email_digest: form_data.email_digest,
+        reminder_channel: form_data.reminder_channel.to_string(),
+    };
+    match set_preferences(db, user.id.unwrap(), &new).await {
+        Ok(_) => Flash::success(preferences, "Preferences saved"),
+        Err(_) => Flash::error(preferences, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("mail stage", |rocket| async {
+        let figment = rocket.figment();
+        let config = match figment.find_value("mail").is_ok() {
+            true => figment.extract_inner("mail").map_err(|e| e.to_string()),
+            false => Ok(MailConfig::default()),
+        };
+        let mailer = match config.and_then(|config| Mailer::from_config(&config)) {
+            Ok(mailer) => mailer,
+            Err(e) => {
+                error!("Failed to set up mail: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let templates = mailer.templates.clone();
+        Ok(rocket
+            .manage(mailer)
+            .attach(Template::custom(move |engines| {
+                // this is the app's only template fairing, so it sets up the pages too
+                engines
+                    .tera
+                    .register_filter("timestamp", db::timestamp_filter);
+                match templates.write() {
+                    Ok(mut templates) => *templates = engines.tera.clone(),
+                    Err(e) => error!("Failed to share the email templates: {}", e),
+                }
+            }))
+            .mount(
+                "/",
+                routes![preferences_get, preferences_get_no_auth, preferences_post],
+            ))
+    })
+}
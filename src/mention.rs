@@ -0,0 +1,198 @@
+use crate::db::DbConnection;
+use crate::notification::notify;
+use crate::user::User;
+use rocket_db_pools::sqlx;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mention {
+    // the text after the `@`
+    pub handle: String,
+    // the project member it refers to, None when nobody matches
+    pub user: Option<i64>,
+}
+
+fn is_handle_char(c: char) -> bool {
+    c.is_alphanumeric() || "._-+@".contains(c)
+}
+
+// `@handle` is only a mention at the start of the text or after something that
+// can't be part of a handle, so `bob@example.com` on its own is left alone
+fn scan_mentions(text: &str) -> Vec<(usize, &str)> {
+    let mut mentions = vec![];
+    let mut rest = 0;
+    for (start, c) in text.char_indices() {
+        if c != '@' || start < rest {
+            continue;
+        }
+        if text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(is_handle_char)
+        {
+            continue;
+        }
+        let after = &text[start + 1..];
+        let len = after.find(|c| !is_handle_char(c)).unwrap_or(after.len());
+        // a sentence ending right after the mention isn't part of it
+        let handle = after[..len].trim_end_matches(['.', '-']);
+        if !handle.is_empty() {
+            mentions.push((start, handle));
+            rest = start + 1 + handle.len();
+        }
+    }
+    mentions
+}
+
+// handles match a member's email, or their name with the spaces left out
+fn find_member(members: &[User], handle: &str) -> Option<i64> {
+    members
+        .iter()
+        .find(|member| {
+            member.email.eq_ignore_ascii_case(handle)
+                || member
+                    .name
+                    .split_whitespace()
+                    .collect::<String>()
+                    .eq_ignore_ascii_case(handle)
+        })
+        .and_then(|member| member.id)
+}
+
+// every distinct mention in `text`, in the order they first appear
+pub fn resolve_mentions(text: &str, members: &[User]) -> Vec<Mention> {
+    let mut mentions: Vec<Mention> = vec![];
+    for (_, handle) in scan_mentions(text) {
+        if !mentions.iter().any(|m| m.handle == handle) {
+            mentions.push(Mention {
+                handle: handle.to_string(),
+                user: find_member(members, handle),
+            });
+        }
+    }
+    mentions
+}
+
+fn link_text(text: &str, members: &[User], out: &mut String) {
+    let mut last = 0;
+    for (start, handle) in scan_mentions(text) {
+        out.push_str(&text[last..start]);
+        match find_member(members, handle) {
+            Some(id) => out.push_str(&format!("<a href=\"/user/{}\">@{}</a>", id, handle)),
+            None => out.push_str(&format!(
+                "<mark title=\"not a member of this project\">@{}</mark>",
+                handle
+            )),
+        }
+        last = start + 1 + handle.len();
+    }
+    out.push_str(&text[last..]);
+}
+
+// elements whose text is left as it is: code is meant literally and links can't nest
+const VERBATIM_TAGS: [&str; 3] = ["a", "code", "pre"];
+
+// turns mentions in already escaped html into links to the member, mentions
+// nobody in the project answers to are marked instead. tags are copied as they
+// are so that urls in attributes stay intact, and so is the text inside
+// VERBATIM_TAGS
+pub fn link_mentions(html: &str, members: &[User]) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut verbatim: usize = 0;
+    while let Some(open) = rest.find('<') {
+        match verbatim {
+            0 => link_text(&rest[..open], members, &mut out),
+            _ => out.push_str(&rest[..open]),
+        }
+        let close = rest[open..].find('>').map_or(rest.len(), |i| open + i + 1);
+        let tag = &rest[open + 1..close];
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let name = tag
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        if VERBATIM_TAGS.iter().any(|t| t.eq_ignore_ascii_case(name)) {
+            match closing {
+                true => verbatim = verbatim.saturating_sub(1),
+                false => verbatim += 1,
+            }
+        }
+        out.push_str(&rest[open..close]);
+        rest = &rest[close..];
+    }
+    match verbatim {
+        0 => link_text(rest, members, &mut out),
+        _ => out.push_str(rest),
+    }
+    out
+}
+
+// notifies members mentioned in `text` that weren't already mentioned in
+// `previous`, which is empty for new tasks and comments
+pub async fn notify_mentions(
+    db: &mut DbConnection,
+    members: &[User],
+    author: i64,
+    text: &str,
+    previous: &str,
+    what: &str,
+    link: &str,
+) -> Result<(), sqlx::Error> {
+    let before = resolve_mentions(previous, members);
+    let author_name = members
+        .iter()
+        .find(|member| member.id == Some(author))
+        .map_or("Someone", |member| member.name.as_str());
+    let message = format!("{} mentioned you in {}", author_name, what);
+
+    for mention in resolve_mentions(text, members) {
+        let user = match mention.user {
+            Some(user) if user != author => user,
+            _ => continue,
+        };
+        if before.iter().any(|m| m.user == Some(user)) {
+            continue;
+        }
+        notify(db, user, "mention", &message, link).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bob() -> User {
+        User {
+            id: Some(2),
+            email: "bob@example.com".to_string(),
+            name: "Bob".to_string(),
+            password: String::new(),
+            created: String::new(),
+            profile_pic: String::new(),
+            admin: false,
+            premium: false,
+            unread: 0,
+        }
+    }
+
+    #[test]
+    fn mentions_in_code_and_links_are_left_alone() {
+        let html = "<p>@bob see <code>@bob</code> and \
+            <a href=\"https://example.com/@bob\">@bob</a></p>";
+        assert_eq!(
+            link_mentions(html, &[bob()]),
+            "<p><a href=\"/user/2\">@bob</a> see <code>@bob</code> and \
+            <a href=\"https://example.com/@bob\">@bob</a></p>"
+        );
+        let html = "<pre><code>@nobody</code></pre><p>@nobody</p>";
+        assert_eq!(
+            link_mentions(html, &[bob()]),
+            "<pre><code>@nobody</code></pre>\
+            <p><mark title=\"not a member of this project\">@nobody</mark></p>"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+// Prometheus metrics at /metrics. Request counters and latencies are kept in
+// memory and reset on restart, the business gauges are read from the database
+// on every scrape.
+use crate::db::{self, DbPool};
+use crate::error::AppResult;
+use crate::request_id;
+use crate::user::{Admin, Db, User};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::outcome::IntoOutcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Deserialize;
+use rocket::{Response, State};
+use rocket_db_pools::sqlx::Row;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// upper bounds of the latency histogram, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// how recently a user must have made a request to count as active
+const ACTIVE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MetricsConfig {
+    // lets a scraper in with `Authorization: Bearer <token>`, admins get in regardless
+    token: Option<String>,
+    // sqlx doesn't report the pool's limit, so it's read from the database config
+    #[serde(skip)]
+    max_connections: usize,
+}
+
+#[derive(Default)]
+struct RouteStats {
+    count: u64,
+    // not cumulative, rendering adds them up
+    buckets: [u64; BUCKETS.len()],
+    seconds: f64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    // keyed by method, route and status
+    requests: Mutex<HashMap<(String, String, u16), RouteStats>>,
+    logins_succeeded: AtomicU64,
+    logins_failed: AtomicU64,
+    last_seen: Mutex<HashMap<i64, Instant>>,
+}
+
+impl Metrics {
+    fn observe(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default();
+        stats.count += 1;
+        stats.seconds += seconds;
+        if let Some(i) = BUCKETS.iter().position(|le| seconds <= *le) {
+            stats.buckets[i] += 1;
+        }
+    }
+
+    fn seen(&self, user_id: i64) {
+        self.last_seen
+            .lock()
+            .unwrap()
+            .insert(user_id, Instant::now());
+    }
+
+    fn active_users(&self) -> usize {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        last_seen.retain(|_, seen| seen.elapsed() < ACTIVE_WINDOW);
+        last_seen.len()
+    }
+
+    pub fn login(&self, succeeded: bool) {
+        let counter = match succeeded {
+            true => &self.logins_succeeded,
+            false => &self.logins_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        let requests = self.requests.lock().unwrap();
+        let mut keys: Vec<_> = requests.keys().collect();
+        keys.sort();
+
+        out.push_str("# HELP rrs_http_requests_total Requests handled, by route and status.\n");
+        out.push_str("# TYPE rrs_http_requests_total counter\n");
+        for key in &keys {
+            let (method, route, status) = key;
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                method,
+                escape(route),
+                status
+            );
+            let _ = writeln!(
+                out,
+                "rrs_http_requests_total{{{}}} {}",
+                labels, requests[*key].count
+            );
+        }
+
+        out.push_str(
+            "# HELP rrs_http_request_duration_seconds Time to respond, by route and status.\n",
+        );
+        out.push_str("# TYPE rrs_http_request_duration_seconds histogram\n");
+        for key in &keys {
+            let (method, route, status) = key;
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                method,
+                escape(route),
+                status
+            );
+            let stats = &requests[*key];
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "rrs_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "rrs_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, stats.count
+            );
+            let _ = writeln!(
+                out,
+                "rrs_http_request_duration_seconds_sum{{{}}} {}",
+                labels, stats.seconds
+            );
+            let _ = writeln!(
+                out,
+                "rrs_http_request_duration_seconds_count{{{}}} {}",
+                labels, stats.count
+            );
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let metrics = match request.rocket().state::<Metrics>() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        // the route pattern, not the path, keeps the number of series bounded
+        let route = request
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        metrics.observe(
+            request.method().as_str(),
+            &route,
+            response.status().code,
+            request_id::elapsed(request),
+        );
+        if let Some(user_id) = request
+            .local_cache(|| None::<User>)
+            .as_ref()
+            .and_then(|user| user.id)
+        {
+            metrics.seen(user_id);
+        }
+    }
+}
+
+// admins, or whoever has the configured token
+pub struct MetricsAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MetricsAccess {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .rocket()
+            .state::<MetricsConfig>()
+            .and_then(|config| config.token.as_deref());
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let (Some(token), Some(bearer)) = (token, bearer) {
+            return (token == bearer)
+                .then_some(MetricsAccess)
+                .into_outcome((Status::Unauthorized, ()));
+        }
+
+        match request.guard::<Admin>().await {
+            Outcome::Success(_) => Outcome::Success(MetricsAccess),
+            // logged in, just not an admin
+            _ if request.guard::<&User>().await.is_success() => {
+                Outcome::Failure((Status::Forbidden, ()))
+            }
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[get("/metrics")]
+async fn metrics_get(
+    _access: MetricsAccess,
+    metrics: &State<Metrics>,
+    config: &State<MetricsConfig>,
+    db: &State<Db>,
+) -> AppResult<(ContentType, String)> {
+    let pool: &DbPool = db;
+    let row = db::query(&format!(
+        "SELECT
+            (SELECT COUNT(*) FROM \"user\") AS users,
+            (SELECT COUNT(*) FROM proj_tasks WHERE task_end_date = '') AS open_tasks,
+            (SELECT COUNT(*) FROM proj_tasks WHERE task_end_date >= {}) AS completed_today",
+        db::TODAY
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    let mut out = String::new();
+    metrics.render_requests(&mut out);
+
+    out.push_str("# HELP rrs_logins_total Login attempts, by outcome.\n");
+    out.push_str("# TYPE rrs_logins_total counter\n");
+    let _ = writeln!(
+        out,
+        "rrs_logins_total{{outcome=\"success\"}} {}",
+        metrics.logins_succeeded.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "rrs_logins_total{{outcome=\"failure\"}} {}",
+        metrics.logins_failed.load(Ordering::Relaxed)
+    );
+
+    let size = pool.size();
+    let idle = pool.num_idle() as u32;
+    out.push_str("# HELP rrs_db_pool_connections Open database connections, by state.\n");
+    out.push_str("# TYPE rrs_db_pool_connections gauge\n");
+    let _ = writeln!(out, "rrs_db_pool_connections{{state=\"idle\"}} {}", idle);
+    let _ = writeln!(
+        out,
+        "rrs_db_pool_connections{{state=\"in_use\"}} {}",
+        size.saturating_sub(idle)
+    );
+    gauge(
+        &mut out,
+        "rrs_db_pool_max_connections",
+        "Connections the pool may open.",
+        config.max_connections,
+    );
+
+    gauge(
+        &mut out,
+        "rrs_users",
+        "Registered users.",
+        row.get::<i64, _>("users"),
+    );
+    gauge(
+        &mut out,
+        "rrs_active_users",
+        "Users who made a request in the last 15 minutes.",
+        metrics.active_users(),
+    );
+    gauge(
+        &mut out,
+        "rrs_open_tasks",
+        "Tasks not completed yet.",
+        row.get::<i64, _>("open_tasks"),
+    );
+    gauge(
+        &mut out,
+        "rrs_tasks_completed_today",
+        "Tasks completed since midnight UTC.",
+        row.get::<i64, _>("completed_today"),
+    );
+
+    Ok((ContentType::Plain, out))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("metrics stage", |rocket| async {
+        let mut config: MetricsConfig = rocket
+            .figment()
+            .extract_inner("metrics")
+            .unwrap_or_default();
+        config.max_connections = rocket
+            .figment()
+            .extract_inner::<rocket_db_pools::Config>("databases.dev-db")
+            .map(|db| db.max_connections)
+            .unwrap_or_default();
+        rocket
+            .manage(config)
+            .manage(Metrics::default())
+            .attach(RequestMetrics)
+            .mount("/", routes![metrics_get])
+    })
+}
@@ -0,0 +1,264 @@
+use crate::db::{self, DbConnection, DbRow};
+use crate::get_flash_msg;
+use crate::user::{require_member, Db, User};
+use rocket::fairing::AdHoc;
+use rocket::request::FlashMessage;
+use rocket::response::{Flash, Redirect};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{sqlx, sqlx::Row, Connection};
+use rocket_dyn_templates::{context, Template};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Notification {
+    pub id: i64,
+    pub user_id: i64,
+    // what happened: 'mention', 'assignment', 'completion', 'membership' or 'reminder'
+    pub kind: String,
+    pub message: String,
+    pub link: String,
+    pub created: String,
+    pub read: bool,
+}
+
+fn serialize_notification(row: &DbRow) -> Notification {
+    Notification {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        kind: row.get("kind"),
+        message: row.get("message"),
+        link: row.get("link"),
+        created: row.get("created"),
+        read: row.get("read"),
+    }
+}
+
+pub async fn notify(
+    db: &mut DbConnection,
+    user_id: i64,
+    kind: &str,
+    message: &str,
+    link: &str,
+) -> Result<(), sqlx::Error> {
+    db::query(
+        "INSERT INTO notification (user_id, kind, message, link, created) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(kind)
+    .bind(message)
+    .bind(link)
+    .bind(db::now())
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// tells everyone watching the task, except whoever caused it, that it was completed
+pub async fn notify_watchers(
+    db: &mut DbConnection,
+    task_id: i64,
+    except: i64,
+) -> Result<(), sqlx::Error> {
+    let row = db::query("SELECT description, owner_proj FROM proj_tasks WHERE id = ?")
+        .bind(task_id)
+        .fetch_one(&mut *db)
+        .await?;
+    let message = format!(
+        "Task \"{}\" was completed",
+        row.get::<String, _>("description")
+    );
+    let link = format!("/project/{}", row.get::<i64, _>("owner_proj"));
+
+    let watchers: Vec<i64> =
+        db::query("SELECT user_id FROM task_watch WHERE task_id = ? AND user_id <> ?")
+            .bind(task_id)
+            .bind(except)
+            .fetch_all(&mut *db)
+            .await?
+            .iter()
+            .map(|row| row.get("user_id"))
+            .collect();
+    for watcher in watchers {
+        notify(db, watcher, "completion", &message, &link).await?;
+    }
+    Ok(())
+}
+
+pub async fn watch_task(
+    db: &mut DbConnection,
+    task_id: i64,
+    user_id: i64,
+) -> Result<(), sqlx::Error> {
+    db::query(
+        "INSERT INTO task_watch (task_id, user_id) VALUES (?, ?)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(task_id)
+    .bind(user_id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn unwatch_task(
+    mut db: Connection<Db>,
+    task_id: i64,
+    user_id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query("DELETE FROM task_watch WHERE task_id = ? AND user_id = ?")
+        .bind(task_id)
+        .bind(user_id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+pub async fn get_notifications_for_user(
+    mut db: Connection<Db>,
+    user_id: i64,
+) -> Result<Vec<Notification>, String> {
+    let result = db::query(
+        "SELECT * FROM notification WHERE user_id = ? ORDER BY created DESC, id DESC LIMIT 100",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *db)
+    .await;
+    match result {
+        Ok(rows) => Ok(rows.iter().map(serialize_notification).collect()),
+        Err(e) => Err(format!("Failed to get notifications: {}", e)),
+    }
+}
+
+// marks the notification read or unread and hands back where it links to
+pub async fn set_notification_read(
+    mut db: Connection<Db>,
+    user_id: i64,
+    id: i64,
+    read: bool,
+) -> Result<Option<String>, sqlx::Error> {
+    let row =
+        db::query("UPDATE notification SET read = ? WHERE id = ? AND user_id = ? RETURNING link")
+            .bind(read)
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut *db)
+            .await?;
+
+    Ok(row.map(|row| row.get("link")))
+}
+
+pub async fn mark_all_read(mut db: Connection<Db>, user_id: i64) -> Result<u64, sqlx::Error> {
+    let result = db::query("UPDATE notification SET read = TRUE WHERE user_id = ? AND NOT read")
+        .bind(user_id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[get("/notifications")]
+async fn notifications_get(
+    db: Connection<Db>,
+    user: &User,
+    flash: Option<FlashMessage<'_>>,
+) -> Template {
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    let notifications = get_notifications_for_user(db, user.id.unwrap())
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            vec![]
+        });
+    Template::render("notifications", context! {user, notifications, msg})
+}
+
+#[get("/notifications", rank = 2)]
+async fn notifications_get_no_auth() -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+// following a notification marks it read
+#[get("/notifications/<id>")]
+async fn open_notification(db: Connection<Db>, user: &User, id: i64) -> Redirect {
+    match set_notification_read(db, user.id.unwrap(), id, true).await {
+        Ok(Some(link)) if !link.is_empty() => Redirect::to(link),
+        _ => Redirect::to(uri!(notifications_get)),
+    }
+}
+
+#[get("/notifications/<id>/read")]
+async fn read_notification(db: Connection<Db>, user: &User, id: i64) -> Flash<Redirect> {
+    let notifications = Redirect::to(uri!(notifications_get));
+    match set_notification_read(db, user.id.unwrap(), id, true).await {
+        Ok(Some(_)) => Flash::success(notifications, "Marked as read"),
+        _ => Flash::error(notifications, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/notifications/<id>/unread")]
+async fn unread_notification(db: Connection<Db>, user: &User, id: i64) -> Flash<Redirect> {
+    let notifications = Redirect::to(uri!(notifications_get));
+    match set_notification_read(db, user.id.unwrap(), id, false).await {
+        Ok(Some(_)) => Flash::success(notifications, "Marked as unread"),
+        _ => Flash::error(notifications, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/notifications/read-all")]
+async fn read_all_notifications(db: Connection<Db>, user: &User) -> Flash<Redirect> {
+    let notifications = Redirect::to(uri!(notifications_get));
+    match mark_all_read(db, user.id.unwrap()).await {
+        Ok(_) => Flash::success(notifications, "All notifications marked as read"),
+        Err(_) => Flash::error(notifications, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/watch/project/<proj_id>/task/<task_id>")]
+async fn watch(mut db: Connection<Db>, user: &User, proj_id: i64, task_id: i64) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match watch_task(&mut db, task_id, user.id.unwrap()).await {
+        Ok(_) => Flash::success(project, "Watching task"),
+        Err(_) => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/unwatch/project/<proj_id>/task/<task_id>")]
+async fn unwatch(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, Some(task_id)).await {
+        return Flash::error(project, e.to_string());
+    }
+    match unwatch_task(db, task_id, user.id.unwrap()).await {
+        Ok(Some(_)) => Flash::success(project, "Stopped watching task"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("notification stage", |rocket| async {
+        rocket.mount(
+            "/",
+            routes![
+                notifications_get,
+                notifications_get_no_auth,
+                open_notification,
+                read_all_notifications,
+                read_notification,
+                unread_notification,
+                unwatch,
+                watch,
+            ],
+        )
+    })
+}
@@ -0,0 +1,620 @@
+// Recurring tasks. A series holds an RFC 5545 RRULE and always has one current
+// task; the recurrence job creates the next instance once that task is completed,
+// deleted or due. Supported rule parts: FREQ (DAILY, WEEKLY, MONTHLY, YEARLY),
+// INTERVAL, COUNT, UNTIL, BYDAY (e.g. MO,WE or 1MO,-1FR for monthly rules) and
+// BYMONTHDAY (e.g. 1,15,-1).
+use crate::db::{self, DbConnection, DbPool, DbRow};
+use crate::live::{Live, ProjectEvent};
+use crate::user::{require_member, Db, User};
+use crate::webhook::queue_event;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc, Weekday};
+use rocket::fairing::AdHoc;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{sqlx, sqlx::Acquire, sqlx::Row, Connection};
+
+// occurrences are searched for at most this far past the newest one
+const HORIZON_YEARS: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    pub freq: Freq,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDateTime>,
+    // 0 means every such weekday, otherwise the nth (or nth-from-last) in the month
+    pub by_day: Vec<(i32, Weekday)>,
+    pub by_month_day: Vec<i32>,
+}
+
+fn parse_weekday(code: &str) -> Result<Weekday, String> {
+    match code {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("unknown weekday {}", code)),
+    }
+}
+
+fn parse_until(value: &str) -> Result<NaiveDateTime, String> {
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .map(|date| date.and_hms_opt(23, 59, 59).unwrap())
+        })
+        .map_err(|_| format!("bad UNTIL {}", value))
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        month => (date.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .unwrap()
+        .pred_opt()
+        .unwrap()
+        .day()
+}
+
+impl RRule {
+    pub fn parse(rule: &str) -> Result<RRule, String> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut freq = None;
+        let mut parsed = RRule {
+            freq: Freq::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+        };
+
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE, got {}", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        other => return Err(format!("unsupported FREQ {}", other)),
+                    })
+                }
+                "INTERVAL" => {
+                    parsed.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| format!("bad INTERVAL {}", value))?
+                }
+                "COUNT" => {
+                    parsed.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or_else(|| format!("bad COUNT {}", value))?,
+                    )
+                }
+                "UNTIL" => parsed.until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let day = day.to_ascii_uppercase();
+                        let (nth, code) = day.split_at(day.len().saturating_sub(2));
+                        let nth = match nth {
+                            "" => 0,
+                            nth => nth
+                                .trim_start_matches('+')
+                                .parse::<i32>()
+                                .ok()
+                                .filter(|nth| (1..=5).contains(&nth.abs()))
+                                .ok_or_else(|| format!("bad BYDAY {}", day))?,
+                        };
+                        parsed.by_day.push((nth, parse_weekday(code)?));
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for day in value.split(',') {
+                        parsed.by_month_day.push(
+                            day.parse::<i32>()
+                                .ok()
+                                .filter(|day| *day != 0 && (-31..=31).contains(day))
+                                .ok_or_else(|| format!("bad BYMONTHDAY {}", day))?,
+                        );
+                    }
+                }
+                "WKST" if value.eq_ignore_ascii_case("MO") => {}
+                other => return Err(format!("unsupported rule part {}", other)),
+            }
+        }
+
+        parsed.freq = freq.ok_or("FREQ is required")?;
+        if parsed.count.is_some() && parsed.until.is_some() {
+            return Err("COUNT and UNTIL can't be combined".to_string());
+        }
+        if parsed.by_day.iter().any(|(nth, _)| *nth != 0) && parsed.freq != Freq::Monthly {
+            return Err("numbered BYDAY values only work with FREQ=MONTHLY".to_string());
+        }
+        Ok(parsed)
+    }
+
+    // whether `date` is part of the series starting on `start`
+    fn matches(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        let interval = self.interval as i64;
+        let weekday_ok = |nth_allowed: bool| {
+            self.by_day.iter().any(|(nth, weekday)| {
+                if date.weekday() != *weekday {
+                    return false;
+                }
+                match *nth {
+                    0 => true,
+                    _ if !nth_allowed => false,
+                    nth if nth > 0 => ((date.day() - 1) / 7 + 1) as i32 == nth,
+                    nth => ((days_in_month(date) - date.day()) / 7 + 1) as i32 == -nth,
+                }
+            })
+        };
+        let month_day_ok = || {
+            let last = days_in_month(date) as i32;
+            self.by_month_day.iter().any(|day| {
+                let day = if *day < 0 { last + 1 + day } else { *day };
+                day == date.day() as i32
+            })
+        };
+
+        match self.freq {
+            Freq::Daily => {
+                (date - start).num_days() % interval == 0
+                    && (self.by_day.is_empty() || weekday_ok(false))
+                    && (self.by_month_day.is_empty() || month_day_ok())
+            }
+            Freq::Weekly => {
+                let monday =
+                    |d: NaiveDate| d - Duration::days(d.weekday().num_days_from_monday() as i64);
+                let weeks = (monday(date) - monday(start)).num_days() / 7;
+                weeks % interval == 0
+                    && if self.by_day.is_empty() {
+                        date.weekday() == start.weekday()
+                    } else {
+                        weekday_ok(false)
+                    }
+            }
+            Freq::Monthly => {
+                let months = (date.year() as i64 * 12 + date.month() as i64)
+                    - (start.year() as i64 * 12 + start.month() as i64);
+                months % interval == 0
+                    && match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+                        (true, true) => date.day() == start.day(),
+                        (false, true) => month_day_ok(),
+                        (true, false) => weekday_ok(true),
+                        (false, false) => month_day_ok() && weekday_ok(true),
+                    }
+            }
+            Freq::Yearly => {
+                (date.year() - start.year()) as i64 % interval == 0
+                    && date.month() == start.month()
+                    && date.day() == start.day()
+            }
+        }
+    }
+
+    // the first occurrence strictly after `after` in the series starting at `dtstart`,
+    // whose occurrence number `seen` was `last`. the search starts at `last` and
+    // counts the occurrences it passes, so it returns the next one with its number,
+    // or None once COUNT or UNTIL are exhausted
+    pub fn next_after(
+        &self,
+        dtstart: NaiveDateTime,
+        last: NaiveDateTime,
+        seen: u32,
+        after: NaiveDateTime,
+    ) -> Option<(NaiveDateTime, u32)> {
+        let start = dtstart.date();
+        let mut date = last.date();
+        let limit = date + Duration::days(HORIZON_YEARS * 366);
+        let mut seen = seen;
+        while date < limit {
+            let occurrence = date.and_time(dtstart.time());
+            if occurrence > last && self.matches(start, date) {
+                seen += 1;
+                if self.count.is_some_and(|count| seen > count)
+                    || self.until.is_some_and(|until| occurrence > until)
+                {
+                    return None;
+                }
+                if occurrence > after {
+                    return Some((occurrence, seen));
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+fn parse_occurrence(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, db::TIMESTAMP_FORMAT).ok()
+}
+
+fn format_occurrence(value: NaiveDateTime) -> String {
+    value.format(db::TIMESTAMP_FORMAT).to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Series {
+    pub id: i64,
+    pub project_id: i64,
+    pub rrule: String,
+    pub dtstart: String,
+    pub description: String,
+    pub priority: i64,
+    // due date of the newest instance
+    pub last_occurrence: String,
+    // which occurrence last_occurrence is, COUNT is checked against it
+    pub occurrences: i64,
+    pub current_task: Option<i64>,
+    // 'active', 'paused' or 'ended'
+    pub status: String,
+    // the occurrence after last_occurrence, if there is one
+    #[serde(skip_deserializing)]
+    pub next_occurrence: Option<String>,
+}
+
+fn serialize_series(row: &DbRow) -> Series {
+    let mut series = Series {
+        id: row.get("id"),
+        project_id: row.get("project_id"),
+        rrule: row.get("rrule"),
+        dtstart: row.get("dtstart"),
+        description: row.get("description"),
+        priority: row.get("priority"),
+        last_occurrence: row.get("last_occurrence"),
+        occurrences: row.get("occurrences"),
+        current_task: row.get("current_task"),
+        status: row.get("status"),
+        next_occurrence: None,
+    };
+    series.next_occurrence =
+        next_occurrence(&series, None).map(|(next, _)| format_occurrence(next));
+    series
+}
+
+// the occurrence following the series' newest one, or following `not_before` if later,
+// together with its number
+fn next_occurrence(
+    series: &Series,
+    not_before: Option<NaiveDateTime>,
+) -> Option<(NaiveDateTime, u32)> {
+    let rrule = RRule::parse(&series.rrule).ok()?;
+    let dtstart = parse_occurrence(&series.dtstart)?;
+    let last = parse_occurrence(&series.last_occurrence)?;
+    let after = not_before.map_or(last, |not_before| last.max(not_before));
+    rrule.next_after(dtstart, last, series.occurrences as u32, after)
+}
+
+// every series of the project from the url, same as for ProjectTasks
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ProjectSeries(pub Vec<Series>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ProjectSeries {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let proj_id = request.param(1).unwrap().unwrap();
+        let db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("could not establish db connection");
+        match get_series_for_project(db, proj_id).await {
+            Ok(series) => Outcome::Success(ProjectSeries(series)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+pub async fn get_series_for_project(
+    mut db: Connection<Db>,
+    proj_id: i64,
+) -> Result<Vec<Series>, String> {
+    let result = db::query("SELECT * FROM task_recurrence WHERE project_id = ? ORDER BY id")
+        .bind(proj_id)
+        .fetch_all(&mut *db)
+        .await;
+    match result {
+        Ok(rows) => Ok(rows.iter().map(serialize_series).collect()),
+        Err(e) => Err(format!("Failed to get recurring tasks: {}", e)),
+    }
+}
+
+// turns a freshly added task into the first instance of a series. the series
+// starts at the task's due date, or now if it has none
+pub async fn start_series(
+    db: &mut DbConnection,
+    task_id: i64,
+    rrule: &str,
+) -> Result<(), sqlx::Error> {
+    let task = db::query(
+        "SELECT owner_proj, description, priority, due_date FROM proj_tasks WHERE id = ?",
+    )
+    .bind(task_id)
+    .fetch_one(&mut *db)
+    .await?;
+    let dtstart = task
+        .get::<Option<String>, _>("due_date")
+        .unwrap_or_else(|| format_occurrence(Utc::now().naive_utc()));
+
+    let series = db::query(
+        "INSERT INTO task_recurrence
+            (project_id, rrule, dtstart, description, priority, last_occurrence, current_task, created)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id",
+    )
+    .bind(task.get::<i64, _>("owner_proj"))
+    .bind(rrule.trim())
+    .bind(&dtstart)
+    .bind(task.get::<String, _>("description"))
+    .bind(task.get::<i64, _>("priority"))
+    .bind(&dtstart)
+    .bind(task_id)
+    .bind(db::now())
+    .fetch_one(&mut *db)
+    .await?;
+    db::query("UPDATE proj_tasks SET recurrence_id = ?, due_date = ? WHERE id = ?")
+        .bind(series.get::<i64, _>("id"))
+        .bind(&dtstart)
+        .bind(task_id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+// creates the next instance of every active series whose current task is done,
+// gone or due; series that run out of occurrences end
+pub async fn advance_series(pool: &DbPool, live: &Live) -> Result<(), sqlx::Error> {
+    let now = Utc::now().naive_utc();
+    let rows = db::query(
+        "SELECT r.*,
+            t.id IS NULL OR t.task_end_date <> '' OR r.last_occurrence <= ? AS advance,
+            COALESCE(t.description, r.description) AS next_description,
+            COALESCE(t.priority, r.priority) AS next_priority,
+            t.assignee
+        FROM task_recurrence r
+        LEFT JOIN proj_tasks t ON t.id = r.current_task
+        WHERE r.status = 'active'",
+    )
+    .bind(format_occurrence(now))
+    .fetch_all(pool)
+    .await?;
+
+    for row in rows {
+        if !row.get::<bool, _>("advance") {
+            continue;
+        }
+        let series = serialize_series(&row);
+        let mut tx = pool.begin().await?;
+
+        // a series that was paused or fell behind picks up after now, so the new
+        // instance isn't due yet and the next run leaves it alone
+        let (next, occurrences) = match next_occurrence(&series, Some(now)) {
+            Some(next) => next,
+            None => {
+                db::query("UPDATE task_recurrence SET status = 'ended' WHERE id = ?")
+                    .bind(series.id)
+                    .execute(&mut tx)
+                    .await?;
+                tx.commit().await?;
+                continue;
+            }
+        };
+        let description: String = row.get("next_description");
+        let priority: i64 = row.get("next_priority");
+        let task = db::query(
+            "INSERT INTO proj_tasks
+                (description, task_start_date, owner_proj, position, priority, due_date, assignee, recurrence_id)
+            VALUES (?, ?, ?, (SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ? AND status = 'todo'), ?, ?, ?, ?)
+            RETURNING id",
+        )
+        .bind(&description)
+        .bind(db::now())
+        .bind(series.project_id)
+        .bind(series.project_id)
+        .bind(priority)
+        .bind(format_occurrence(next))
+        .bind(row.get::<Option<i64>, _>("assignee"))
+        .bind(series.id)
+        .fetch_one(&mut tx)
+        .await?;
+        let task_id: i64 = task.get("id");
+        db::query(
+            "UPDATE task_recurrence SET last_occurrence = ?, occurrences = ?, current_task = ?
+            WHERE id = ?",
+        )
+        .bind(format_occurrence(next))
+        .bind(occurrences as i64)
+        .bind(task_id)
+        .bind(series.id)
+        .execute(&mut tx)
+        .await?;
+        let event = ProjectEvent::new(
+            series.project_id,
+            "task.created",
+            Some(task_id),
+            json!({"description": description, "priority": priority, "parent_id": null}),
+        );
+        queue_event(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        live.publish(event);
+    }
+    Ok(())
+}
+
+// moves the current open instance on to the following occurrence
+pub async fn skip_occurrence(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let mut tx = (&mut *db).begin().await?;
+
+    let series = match db::query("SELECT * FROM task_recurrence WHERE id = ? AND project_id = ?")
+        .bind(id)
+        .bind(proj_id)
+        .fetch_optional(&mut tx)
+        .await?
+    {
+        Some(row) => serialize_series(&row),
+        None => return Ok(None),
+    };
+    let (next, occurrences) = match next_occurrence(&series, None) {
+        Some((next, occurrences)) => (format_occurrence(next), occurrences),
+        None => return Ok(None),
+    };
+
+    db::query("UPDATE task_recurrence SET last_occurrence = ?, occurrences = ? WHERE id = ?")
+        .bind(&next)
+        .bind(occurrences as i64)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    db::query("UPDATE proj_tasks SET due_date = ? WHERE id = ? AND task_end_date = ''")
+        .bind(&next)
+        .bind(series.current_task)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(()))
+}
+
+pub async fn set_series_status(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    id: i64,
+    status: &str,
+) -> Result<Option<()>, sqlx::Error> {
+    // ended series stay ended
+    let result = db::query(
+        "UPDATE task_recurrence SET status = ?
+        WHERE id = ? AND project_id = ? AND status <> 'ended'",
+    )
+    .bind(status)
+    .bind(id)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+#[get("/project/<proj_id>/series/<id>/<action>")]
+async fn series_action(
+    mut db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    id: i64,
+    action: &str,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    if let Err(e) = require_member(&mut db, user.id.unwrap(), proj_id, None).await {
+        return Flash::error(project, e.to_string());
+    }
+    let (result, done) = match action {
+        "skip" => (skip_occurrence(db, proj_id, id).await, "Occurrence skipped"),
+        "pause" => (
+            set_series_status(db, proj_id, id, "paused").await,
+            "Recurrence paused",
+        ),
+        "resume" => (
+            set_series_status(db, proj_id, id, "active").await,
+            "Recurrence resumed",
+        ),
+        "end" => (
+            set_series_status(db, proj_id, id, "ended").await,
+            "Recurrence ended",
+        ),
+        _ => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    match result {
+        Ok(Some(_)) => Flash::success(project, done),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("recurrence stage", |rocket| async {
+        rocket.mount("/", routes![series_action])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recurrence_counts_on_from_the_last_occurrence() {
+        let dtstart =
+            NaiveDateTime::parse_from_str("2023-06-01 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let rule = RRule::parse("FREQ=DAILY;INTERVAL=2;COUNT=3").unwrap();
+        let (second, seen) = rule.next_after(dtstart, dtstart, 1, dtstart).unwrap();
+        assert_eq!((second, seen), (dtstart + Duration::days(2), 2));
+        let (third, seen) = rule.next_after(dtstart, second, seen, second).unwrap();
+        assert_eq!((third, seen), (dtstart + Duration::days(4), 3));
+        assert_eq!(rule.next_after(dtstart, third, seen, third), None);
+
+        // open ended series keep going however long they have been running
+        let rule = RRule::parse("FREQ=WEEKLY;BYDAY=MO").unwrap();
+        let last = dtstart + Duration::days(200 * 365);
+        let (next, _) = rule.next_after(dtstart, last, 10_000, last).unwrap();
+        assert_eq!(next.weekday(), Weekday::Mon);
+        assert!(next > last && next - last <= Duration::days(7));
+    }
+
+    #[test]
+    fn a_paused_series_picks_up_after_now() {
+        let now =
+            NaiveDateTime::parse_from_str("2023-06-10 12:00:00", db::TIMESTAMP_FORMAT).unwrap();
+        let series = Series {
+            id: 1,
+            project_id: 1,
+            rrule: "FREQ=DAILY".to_string(),
+            dtstart: "2023-06-01 09:00:00".to_string(),
+            description: "Stand up".to_string(),
+            priority: 0,
+            last_occurrence: "2023-06-02 09:00:00".to_string(),
+            occurrences: 2,
+            current_task: None,
+            status: "active".to_string(),
+            next_occurrence: None,
+        };
+        // an instance due already would be advanced again on the next run
+        let (next, seen) = next_occurrence(&series, Some(now)).unwrap();
+        assert_eq!(format_occurrence(next), "2023-06-11 09:00:00");
+        assert_eq!(seen, 11);
+    }
+}
@@ -0,0 +1,346 @@
+// Task reminders. A reminder fires once, either at a fixed time or a number of
+// minutes before the task's due date, and reaches the user through the channel
+// picked in their preferences: in-app notification, email or webhook.
+
+// rocket's FromForm derive still emits the removed private_in_public lint
+#![allow(renamed_and_removed_lints)]
+
+use crate::comment::PROJECT_MEMBER;
+use crate::db::{self, DbConnection, DbPool, DbRow};
+use crate::live::ProjectEvent;
+use crate::mail::Mailer;
+use crate::notification::notify;
+use crate::user::{parse_date, Db, User};
+use crate::webhook::queue_user_event;
+use rocket::fairing::AdHoc;
+use rocket::form::{Contextual, Form};
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{sqlx, sqlx::Row, Connection};
+
+pub const REMINDER_CHANNELS: [&str; 3] = ["app", "email", "webhook"];
+
+// deliveries tried before a reminder is given up as failed
+const MAX_ATTEMPTS: i64 = 5;
+
+// when a reminder is due: its fixed time, or the offset taken from the current due date
+fn fire_at() -> String {
+    format!(
+        "COALESCE(r.remind_at, {})",
+        db::shifted("t.due_date", "'-' || r.offset_minutes || ' minutes'")
+    )
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Reminder {
+    pub id: i64,
+    pub task_id: i64,
+    pub user_id: i64,
+    // set for absolute reminders, 'YYYY-MM-DD HH:MM:SS' UTC
+    pub remind_at: Option<String>,
+    // set for reminders relative to the due date
+    pub offset_minutes: Option<i64>,
+    // 'pending', 'sent', 'failed' or 'cancelled'
+    pub status: String,
+    // None while a relative reminder's task has no due date
+    #[serde(skip_deserializing)]
+    pub fire_at: Option<String>,
+    pub created: String,
+}
+
+fn serialize_reminder(row: &DbRow) -> Reminder {
+    Reminder {
+        id: row.get("id"),
+        task_id: row.get("task_id"),
+        user_id: row.get("user_id"),
+        remind_at: row.get("remind_at"),
+        offset_minutes: row.get("offset_minutes"),
+        status: row.get("status"),
+        fire_at: row.get("fire_at"),
+        created: row.get("created"),
+    }
+}
+
+// the logged in user's reminders on the project from the url, same as for ProjectTasks
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Reminders(pub Vec<Reminder>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Reminders {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<&User>().await);
+        let proj_id = request.param(1).unwrap().unwrap();
+        let db = request
+            .guard::<Connection<Db>>()
+            .await
+            .succeeded()
+            .expect("could not establish db connection");
+        match get_reminders_for_project(db, user.id.unwrap(), proj_id).await {
+            Ok(reminders) => Outcome::Success(Reminders(reminders)),
+            Err(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+pub async fn get_reminders_for_project(
+    mut db: Connection<Db>,
+    user_id: i64,
+    proj_id: i64,
+) -> Result<Vec<Reminder>, String> {
+    let result = db::query(&format!(
+        "SELECT r.*, {} AS fire_at FROM task_reminder r
+        JOIN proj_tasks t ON t.id = r.task_id
+        WHERE r.user_id = ? AND t.owner_proj = ?
+        ORDER BY r.status = 'pending' DESC, fire_at",
+        fire_at()
+    ))
+    .bind(user_id)
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await;
+    match result {
+        Ok(rows) => Ok(rows.iter().map(serialize_reminder).collect()),
+        Err(e) => Err(format!("Failed to get reminders: {}", e)),
+    }
+}
+
+// exactly one of `remind_at` and `offset_minutes` is expected
+pub async fn add_reminder(
+    mut db: Connection<Db>,
+    user_id: i64,
+    proj_id: i64,
+    task_id: i64,
+    remind_at: Option<String>,
+    offset_minutes: Option<i64>,
+) -> Result<Option<i64>, sqlx::Error> {
+    // only members get reminded, and only of tasks in their project
+    let inserted = db::query(&format!(
+        "INSERT INTO task_reminder (task_id, user_id, remind_at, offset_minutes, created)
+        SELECT ?, ?, ?, ?, ?
+        WHERE {}
+        AND EXISTS (SELECT 1 FROM proj_tasks WHERE id = ?1 AND owner_proj = ? AND task_end_date = '')
+        RETURNING id",
+        PROJECT_MEMBER
+    ))
+    .bind(task_id)
+    .bind(user_id)
+    .bind(remind_at)
+    .bind(offset_minutes)
+    .bind(db::now())
+    .bind(proj_id)
+    .bind(user_id)
+    .bind(user_id)
+    .bind(proj_id)
+    .fetch_optional(&mut *db)
+    .await?;
+
+    Ok(inserted.map(|row| row.get("id")))
+}
+
+pub async fn delete_reminder_db(
+    mut db: Connection<Db>,
+    user_id: i64,
+    proj_id: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query(
+        "DELETE FROM task_reminder WHERE id = ? AND user_id = ?
+        AND task_id IN (SELECT id FROM proj_tasks WHERE owner_proj = ?)",
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+// called when tasks are completed, their reminders have nothing left to say
+pub async fn cancel_reminders(db: &mut DbConnection, task_id: i64) -> Result<(), sqlx::Error> {
+    db::query(
+        "UPDATE task_reminder SET status = 'cancelled' WHERE task_id = ? AND status = 'pending'",
+    )
+    .bind(task_id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+// fires every pending reminder that is due, run by the job runner. a reminder is only
+// marked sent once it went out, failed deliveries are tried again with a growing delay
+// until MAX_ATTEMPTS. the runner never runs this twice at once, so a reminder only
+// goes out twice when the process dies between delivering and marking it
+pub async fn send_due_reminders(pool: &DbPool, mailer: &Mailer) -> Result<(), String> {
+    let fail = |e: sqlx::Error| format!("Failed to send reminders: {}", e);
+
+    let rows = db::query(&format!(
+        "SELECT r.id, r.task_id, r.user_id, t.description, t.due_date, t.owner_proj,
+            u.email, u.name, COALESCE(p.reminder_channel, 'app') AS channel
+        FROM task_reminder r
+        JOIN proj_tasks t ON t.id = r.task_id
+        JOIN \"user\" u ON u.id = r.user_id
+        LEFT JOIN notification_preference p ON p.user_id = r.user_id
+        WHERE r.status = 'pending' AND t.task_end_date = '' AND {} <= {}
+            AND (r.retry_at IS NULL OR r.retry_at <= {})
+        ORDER BY r.id
+        LIMIT 50",
+        fire_at(),
+        db::NOW,
+        db::NOW
+    ))
+    .fetch_all(pool)
+    .await
+    .map_err(fail)?;
+
+    for row in rows {
+        let id: i64 = row.get("id");
+        let task_id: i64 = row.get("task_id");
+        let user_id: i64 = row.get("user_id");
+        let proj_id: i64 = row.get("owner_proj");
+        let description: String = row.get("description");
+        let due_date: Option<String> = row.get("due_date");
+        let message = match &due_date {
+            Some(due_date) => format!("Reminder: task \"{}\" is due {}", description, due_date),
+            None => format!("Reminder: task \"{}\"", description),
+        };
+        let link = format!("/project/{}#task-{}", proj_id, task_id);
+
+        let delivered = match row.get::<String, _>("channel").as_str() {
+            "email" => {
+                let mut context = tera::Context::new();
+                context.insert("name", &row.get::<String, _>("name"));
+                context.insert("message", &message);
+                context.insert("link", &link);
+                let email: String = row.get("email");
+                mailer.send(&email, &message, "reminder", &context).await
+            }
+            "webhook" => {
+                let event = ProjectEvent::new(
+                    proj_id,
+                    "task.reminder",
+                    Some(task_id),
+                    json!({"user": user_id, "description": description, "due_date": due_date}),
+                );
+                match pool.acquire().await {
+                    Ok(mut db) => queue_user_event(&mut db, user_id, &event)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            _ => match pool.acquire().await {
+                Ok(mut db) => notify(&mut db, user_id, "reminder", &message, &link)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        match delivered {
+            Ok(()) => db::query(
+                "UPDATE task_reminder SET status = 'sent' WHERE id = ? AND status = 'pending'",
+            )
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(fail)?,
+            Err(e) => {
+                error!("Failed to deliver reminder {}: {}", id, e);
+                db::query(&format!(
+                    "UPDATE task_reminder
+                    SET attempts = attempts + 1, retry_at = {},
+                        status = CASE WHEN attempts + 1 >= ? THEN 'failed' ELSE status END
+                    WHERE id = ? AND status = 'pending'",
+                    db::shifted(db::NOW, "'+' || ((attempts + 1) * 5) || ' minutes'")
+                ))
+                .bind(MAX_ATTEMPTS)
+                .bind(id)
+                .execute(pool)
+                .await
+                .map_err(fail)?
+            }
+        };
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+struct ReminderForm<'v> {
+    // a datepicker value for a fixed time
+    at: &'v str,
+    // or an amount of `unit` before the due date
+    before: Option<i64>,
+    unit: &'v str,
+}
+
+#[post("/project/<proj_id>/task/<task_id>/reminder", data = "<form>")]
+async fn add_reminder_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, ReminderForm<'r>>>,
+    user: &User,
+    proj_id: i64,
+    task_id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    let form_data = match form.value {
+        Some(ref form_data) => form_data,
+        None => return Flash::error(project, "Hmm... That didn't work 🙃"),
+    };
+    let (remind_at, offset_minutes) = match (form_data.at, form_data.before) {
+        ("", Some(before)) if before >= 0 => {
+            let minutes = match form_data.unit {
+                "minutes" => 1,
+                "hours" => 60,
+                "days" => 60 * 24,
+                _ => return Flash::error(project, "Hmm... That didn't work 🙃"),
+            };
+            (None, Some(before * minutes))
+        }
+        ("", _) => return Flash::error(project, "Pick a time or how long before the due date"),
+        (at, _) => match parse_date(at) {
+            Some(at) => (Some(at), None),
+            None => return Flash::error(project, "Invalid reminder time"),
+        },
+    };
+    match add_reminder(
+        db,
+        user.id.unwrap(),
+        proj_id,
+        task_id,
+        remind_at,
+        offset_minutes,
+    )
+    .await
+    {
+        Ok(Some(_)) => Flash::success(project, "Reminder set"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/reminder/<id>")]
+async fn delete_reminder(
+    db: Connection<Db>,
+    user: &User,
+    proj_id: i64,
+    id: i64,
+) -> Flash<Redirect> {
+    let project = Redirect::to(uri!(crate::project_id(proj_id)));
+    match delete_reminder_db(db, user.id.unwrap(), proj_id, id).await {
+        Ok(Some(_)) => Flash::success(project, "Reminder deleted"),
+        _ => Flash::error(project, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("reminder stage", |rocket| async {
+        rocket.mount("/", routes![add_reminder_post, delete_reminder])
+    })
+}
@@ -0,0 +1,113 @@
+// A short id per request, so what a user reports can be matched with the log,
+// and the fairing that writes one JSON line per request carrying it.
+use crate::user::User;
+use chrono::Utc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::json;
+use rocket::{Data, Response};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn new() -> Self {
+        // the counter keeps ids unique within a run, the time across restarts
+        let n = NEXT_ID.fetch_add(1, Ordering::Relaxed) & 0xffff;
+        RequestId(format!("{:x}{:04x}", Utc::now().timestamp_millis(), n))
+    }
+
+    // an id handed in by a proxy is kept, as long as it is safe to put in a log line
+    fn from_header(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| RequestId(value.to_string()))
+    }
+
+    // the id is made on first use and cached for the rest of the request
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r RequestId {
+        request.local_cache(RequestId::new)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r RequestId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request))
+    }
+}
+
+struct Started(Instant);
+
+// time since the request came in, as seen by the RequestLog fairing
+pub fn elapsed(request: &Request<'_>) -> Duration {
+    request.local_cache(|| Started(Instant::now())).0.elapsed()
+}
+
+pub struct RequestLog;
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| Started(Instant::now()));
+        let id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::new);
+        request.local_cache(|| id);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let id = RequestId::of(request);
+        let latency = elapsed(request);
+        // set by the &User guard, when a handler asked for one
+        let user_id = request
+            .local_cache(|| None::<User>)
+            .as_ref()
+            .and_then(|user| user.id);
+        let status = response.status();
+
+        let line = json!({
+            "request_id": id.0,
+            "method": request.method().as_str(),
+            "path": request.uri().path().as_str(),
+            "route": request.route().map(|route| route.uri.to_string()),
+            "status": status.code,
+            "latency_ms": latency.as_secs_f64() * 1000.0,
+            "user_id": user_id,
+        });
+        if status.class().is_server_error() {
+            error!("{}", line);
+        } else {
+            info!("{}", line);
+        }
+
+        response.set_raw_header(REQUEST_ID_HEADER, id.0.clone());
+    }
+}
@@ -0,0 +1,233 @@
+// Demo data for development and tests. The same seed always produces the same
+// users, projects and tasks, dated from a fixed starting point rather than the
+// clock. Seeded users have `@seed.example` emails and share SEED_PASSWORD.
+use crate::auth::hash_password;
+use crate::db::{self, DbConnection};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use rocket_db_pools::sqlx::{self, Acquire, Row};
+
+pub const SEED_PASSWORD: &str = "password";
+
+const VERBS: [&str; 10] = [
+    "Write", "Review", "Fix", "Plan", "Test", "Design", "Refactor", "Document", "Deploy", "Measure",
+];
+const THINGS: [&str; 10] = [
+    "the login page",
+    "the release notes",
+    "the onboarding flow",
+    "the invoice export",
+    "the search index",
+    "the billing report",
+    "the mobile layout",
+    "the backup script",
+    "the API docs",
+    "the dashboard",
+];
+const PROJECTS: [&str; 8] = [
+    "Website relaunch",
+    "Q3 roadmap",
+    "Customer portal",
+    "Data migration",
+    "Mobile app",
+    "Support tooling",
+    "Office move",
+    "Security audit",
+];
+const NAMES: [&str; 8] = [
+    "Ada", "Grace", "Linus", "Margaret", "Ken", "Barbara", "Dennis", "Frances",
+];
+
+#[derive(Debug, Clone)]
+pub struct SeedConfig {
+    pub seed: u64,
+    pub users: u32,
+    pub projects: u32,
+    // per project, on top of its owner
+    pub participants: u32,
+    // per project
+    pub tasks: u32,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        SeedConfig {
+            seed: 42,
+            users: 8,
+            projects: 4,
+            participants: 2,
+            tasks: 12,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SeedSummary {
+    pub users: u32,
+    pub projects: u32,
+    pub tasks: u32,
+}
+
+// splitmix64, small and stable across platforms and releases, unlike a crate's default rng
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // in low..high
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next() % (high - low) as u64) as i64
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.range(0, items.len() as i64) as usize]
+    }
+
+    fn chance(&mut self, percent: i64) -> bool {
+        self.range(0, 100) < percent
+    }
+}
+
+// everything is dated from here, so reruns produce identical rows
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2023, 1, 2)
+        .and_then(|date| date.and_hms_opt(9, 0, 0))
+        .expect("a valid date")
+}
+
+fn timestamp(at: NaiveDateTime) -> String {
+    at.format(db::TIMESTAMP_FORMAT).to_string()
+}
+
+// returns None when the database already holds seeded data
+pub async fn seed(
+    db: &mut DbConnection,
+    config: &SeedConfig,
+) -> Result<Option<SeedSummary>, sqlx::Error> {
+    let seeded = db::query("SELECT 1 FROM \"user\" WHERE email LIKE '%@seed.example' LIMIT 1")
+        .fetch_optional(&mut *db)
+        .await?;
+    if seeded.is_some() {
+        return Ok(None);
+    }
+
+    let mut rng = Rng(config.seed);
+    let mut summary = SeedSummary::default();
+    // bcrypt is slow on purpose, one hash does for everyone
+    let password = hash_password(SEED_PASSWORD);
+    let mut tx = db.begin().await?;
+
+    let mut user_ids = vec![];
+    for n in 1..=config.users {
+        let created = epoch() - Duration::days(rng.range(30, 365));
+        let name = format!("{} {}", rng.pick(&NAMES), n);
+        let result = db::query(
+            "INSERT INTO \"user\" (name, email, password, created, admin, premium)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id",
+        )
+        .bind(name)
+        .bind(format!("user{}@seed.example", n))
+        .bind(&password)
+        .bind(timestamp(created))
+        // the first user is an admin, to get into everything
+        .bind(n == 1)
+        .bind(rng.chance(25))
+        .fetch_one(&mut tx)
+        .await?;
+        user_ids.push(result.get::<i64, _>("id"));
+        summary.users += 1;
+    }
+    if user_ids.is_empty() {
+        tx.commit().await?;
+        return Ok(Some(summary));
+    }
+
+    for n in 1..=config.projects {
+        let owner = *rng.pick(&user_ids);
+        let mut members = vec![owner];
+        let others = (config.participants as usize).min(user_ids.len() - 1);
+        while members.len() < others + 1 {
+            let user = *rng.pick(&user_ids);
+            if !members.contains(&user) {
+                members.push(user);
+            }
+        }
+        let participants: Vec<String> = members[1..].iter().map(|id| id.to_string()).collect();
+
+        let start = epoch() + Duration::days(rng.range(0, 60));
+        // most projects are still running
+        let end = match rng.chance(25) {
+            true => timestamp(start + Duration::days(rng.range(30, 120))),
+            false => String::new(),
+        };
+        let result = db::query(
+            "INSERT INTO project (name, proj_start_date, proj_end_date, owner, participants)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING id",
+        )
+        .bind(format!("{} {}", rng.pick(&PROJECTS), n))
+        .bind(timestamp(start))
+        .bind(end)
+        .bind(owner)
+        .bind(participants.join(","))
+        .fetch_one(&mut tx)
+        .await?;
+        let proj_id: i64 = result.get("id");
+        summary.projects += 1;
+
+        let mut positions = [0; 4];
+        for _ in 0..config.tasks {
+            let task_start = start + Duration::minutes(rng.range(0, 60 * 24 * 30));
+            let (status, task_end, time_delta) = match rng.range(0, 10) {
+                0..=4 => {
+                    let took = Duration::minutes(rng.range(30, 60 * 24 * 5));
+                    ("done", timestamp(task_start + took), took.num_seconds())
+                }
+                5 => ("review", String::new(), 0),
+                6 | 7 => ("in_progress", String::new(), 0),
+                _ => ("todo", String::new(), 0),
+            };
+            let column = match status {
+                "todo" => 0,
+                "in_progress" => 1,
+                "review" => 2,
+                _ => 3,
+            };
+            let due_date = rng.chance(50).then(|| {
+                (task_start + Duration::days(rng.range(1, 21)))
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            });
+            let assignee = rng.chance(70).then(|| *rng.pick(&members));
+
+            db::query(
+                "INSERT INTO proj_tasks (description, task_start_date, task_end_date, owner_proj,
+                    time_delta, status, position, priority, due_date, assignee)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(format!("{} {}", rng.pick(&VERBS), rng.pick(&THINGS)))
+            .bind(timestamp(task_start))
+            .bind(task_end)
+            .bind(proj_id)
+            .bind(time_delta)
+            .bind(status)
+            .bind(positions[column])
+            .bind(rng.range(0, 4))
+            .bind(due_date)
+            .bind(assignee)
+            .execute(&mut tx)
+            .await?;
+            positions[column] += 1;
+            summary.tasks += 1;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(Some(summary))
+}
@@ -0,0 +1,1055 @@
+// End to end tests through Rocket's local client. Every test gets its own
+// database with the migrations applied by the user stage: a temp file on sqlite,
+// or with the `postgres` feature a fresh schema in the database at
+// TEST_DATABASE_URL, e.g.
+//
+//     TEST_DATABASE_URL=postgres://localhost/rrs_test cargo test --features postgres
+use super::rocket;
+use crate::db::{self, DbPool};
+use crate::mail::{send_digests, Email, Mailer, MemoryTransport, Transport};
+use crate::reminder::send_due_reminders;
+use crate::seed::{seed, SeedConfig, SeedSummary};
+use crate::user::Db;
+use crate::webhook::{send_due_deliveries, sign};
+use chrono::{Duration, NaiveDateTime};
+use rocket::error::ErrorKind;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket_db_pools::{sqlx::Row, Database};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static NEXT_DB: AtomicUsize = AtomicUsize::new(0);
+
+fn next_db_name() -> String {
+    format!(
+        "rrs_test_{}_{}",
+        std::process::id(),
+        NEXT_DB.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(not(feature = "postgres"))]
+struct TestDb {
+    path: std::path::PathBuf,
+}
+
+#[cfg(not(feature = "postgres"))]
+impl TestDb {
+    async fn create() -> TestDb {
+        let path = std::env::temp_dir().join(format!("{}.sqlite", next_db_name()));
+        let _ = std::fs::remove_file(&path);
+        TestDb { path }
+    }
+
+    fn url(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(feature = "postgres")]
+struct TestDb {
+    base_url: String,
+    schema: String,
+}
+
+#[cfg(feature = "postgres")]
+impl TestDb {
+    async fn create() -> TestDb {
+        use sqlx::Connection;
+
+        let base_url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL should point at a postgres database to test against");
+        let schema = next_db_name();
+        let mut conn = db::DbConnection::connect(&base_url)
+            .await
+            .expect("TEST_DATABASE_URL should accept connections");
+        db::query(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        db::query(&format!("CREATE SCHEMA {}", schema))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        TestDb { base_url, schema }
+    }
+
+    // every connection of the app starts out in the test's schema
+    fn url(&self) -> String {
+        let separator = if self.base_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!(
+            "{}{}options=-c%20search_path%3D{}",
+            self.base_url, separator, self.schema
+        )
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        use sqlx::Connection;
+
+        // drop runs on the test's runtime, which can't be blocked on, so the
+        // schema is dropped from a thread with a runtime of its own
+        let base_url = self.base_url.clone();
+        let schema = self.schema.clone();
+        let _ = std::thread::spawn(move || {
+            let runtime = rocket::tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("a runtime to drop the test schema");
+            runtime.block_on(async {
+                if let Ok(mut conn) = db::DbConnection::connect(&base_url).await {
+                    let _ = db::query(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+                        .execute(&mut conn)
+                        .await;
+                }
+            });
+        })
+        .join();
+    }
+}
+
+struct TestApp {
+    client: Client,
+    // dropped after the client, which holds the connections to it
+    _db: TestDb,
+}
+
+impl TestApp {
+    async fn new() -> TestApp {
+        let db = TestDb::create().await;
+
+        let rocket = rocket();
+        let figment = rocket
+            .figment()
+            .clone()
+            .merge(("databases.dev-db.url", db.url()));
+        let client = Client::tracked(rocket.configure(figment))
+            .await
+            .expect("valid rocket instance");
+        TestApp { client, _db: db }
+    }
+
+    fn pool(&self) -> &DbPool {
+        Db::fetch(self.client.rocket()).expect("database is attached")
+    }
+
+    async fn post(&self, uri: String, body: String) -> LocalResponse<'_> {
+        self.client
+            .post(uri)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    async fn get(&self, uri: String) -> LocalResponse<'_> {
+        self.client.get(uri).dispatch().await
+    }
+
+    async fn register(&self, name: &str, email: &str, password: &str) {
+        let body = format!(
+            "name={}&email={}&password={}&password_check={}",
+            name, email, password, password
+        );
+        let response = self.post("/add-user".to_string(), body).await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    async fn login(&self, email: &str, password: &str) {
+        let body = format!("email={}&password={}", email, password);
+        self.post("/login".to_string(), body).await;
+        assert!(
+            self.client
+                .cookies()
+                .get_private("user_id_in_cookie")
+                .is_some(),
+            "{} should be logged in",
+            email
+        );
+    }
+
+    async fn logout(&self) {
+        self.get("/logout".to_string()).await;
+    }
+
+    // registers and logs in, returning the new user's id
+    async fn sign_up(&self, name: &str) -> i64 {
+        let email = format!("{}@example.com", name);
+        self.register(name, &email, "hunter2").await;
+        self.login(&email, "hunter2").await;
+        db::query("SELECT id FROM \"user\" WHERE email = ?")
+            .bind(&email)
+            .fetch_one(self.pool())
+            .await
+            .unwrap()
+            .get("id")
+    }
+
+    async fn add_project(&self, name: &str) -> i64 {
+        let response = self
+            .post("/add-project".to_string(), format!("name={}", name))
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        location.trim_start_matches("/project/").parse().unwrap()
+    }
+
+    async fn add_task(&self, proj_id: i64, description: &str) -> i64 {
+        let body = format!("description={}&priority=2&due_date=", description);
+        let response = self
+            .post(format!("/project/{}/add-task", proj_id), body)
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        db::query("SELECT MAX(id) AS id FROM proj_tasks WHERE owner_proj = ?")
+            .bind(proj_id)
+            .fetch_one(self.pool())
+            .await
+            .unwrap()
+            .get("id")
+    }
+
+    async fn count(&self, sql: &str, id: i64) -> i64 {
+        db::query(sql)
+            .bind(id)
+            .fetch_one(self.pool())
+            .await
+            .unwrap()
+            .get(0)
+    }
+}
+
+#[rocket::async_test]
+async fn full_project_flow() {
+    let app = TestApp::new().await;
+    app.sign_up("alice").await;
+
+    let response = app.get("/profile".to_string()).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let proj_id = app.add_project("Launch").await;
+    let response = app.get(format!("/project/{}", proj_id)).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.into_string().await.unwrap().contains("Launch"));
+
+    let task_id = app.add_task(proj_id, "Ship+it").await;
+    let description: String = db::query("SELECT description FROM proj_tasks WHERE id = ?")
+        .bind(task_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("description");
+    assert_eq!(description, "Ship it");
+
+    // backdate the start so the recorded duration is known
+    let started = db::timestamp(chrono::Utc::now() - Duration::hours(1));
+    db::query("UPDATE proj_tasks SET task_start_date = ? WHERE id = ?")
+        .bind(started)
+        .bind(task_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    let response = app
+        .get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let row = db::query("SELECT task_end_date, status, time_delta FROM proj_tasks WHERE id = ?")
+        .bind(task_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_ne!(row.get::<String, _>("task_end_date"), "");
+    assert_eq!(row.get::<String, _>("status"), "done");
+    let time_delta: i64 = row.get("time_delta");
+    assert!(
+        (3600..3660).contains(&time_delta),
+        "time_delta was {}",
+        time_delta
+    );
+
+    let response = app
+        .post(
+            format!("/edit/project/{}", proj_id),
+            "name=Relaunch&end_date=".to_string(),
+        )
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let name: String = db::query("SELECT name FROM project WHERE id = ?")
+        .bind(proj_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("name");
+    assert_eq!(name, "Relaunch");
+
+    let other_task = app.add_task(proj_id, "Write+docs").await;
+    let body = format!(
+        "description=Write+the+docs&priority=1&due_date=&owner_proj={}",
+        proj_id
+    );
+    app.post(
+        format!("/edit/project/{}/task/{}", proj_id, other_task),
+        body,
+    )
+    .await;
+    let row = db::query("SELECT description, priority FROM proj_tasks WHERE id = ?")
+        .bind(other_task)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<String, _>("description"), "Write the docs");
+    assert_eq!(row.get::<i64, _>("priority"), 1);
+
+    app.get(format!("/delete/project/{}/task/{}", proj_id, other_task))
+        .await;
+    let tasks = app
+        .count("SELECT COUNT(*) FROM proj_tasks WHERE id = ?", other_task)
+        .await;
+    assert_eq!(tasks, 0);
+
+    app.get(format!("/delete/project/{}", proj_id)).await;
+    let projects = app
+        .count("SELECT COUNT(*) FROM project WHERE id = ?", proj_id)
+        .await;
+    assert_eq!(projects, 0);
+    // tasks go with their project
+    let tasks = app
+        .count(
+            "SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ?",
+            proj_id,
+        )
+        .await;
+    assert_eq!(tasks, 0);
+}
+
+#[rocket::async_test]
+async fn register_rejects_a_taken_email() {
+    let app = TestApp::new().await;
+    app.register("alice", "alice@example.com", "hunter2").await;
+    let body = "name=eve&email=alice@example.com&password=x&password_check=x".to_string();
+    let response = app.post("/add-user".to_string(), body).await;
+    assert_eq!(response.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+async fn anonymous_access_is_rejected() {
+    let app = TestApp::new().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Secret").await;
+    let task_id = app.add_task(proj_id, "Hidden").await;
+    app.logout().await;
+
+    let response = app.get("/profile".to_string()).await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/login"));
+
+    let response = app.get(format!("/project/{}", proj_id)).await;
+    assert_ne!(response.status(), Status::Ok);
+
+    let response = app
+        .post("/add-project".to_string(), "name=Sneaky".to_string())
+        .await;
+    assert_eq!(response.headers().get_one("Location"), Some("/login"));
+
+    app.get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    app.get(format!("/delete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    app.get(format!("/delete/project/{}", proj_id)).await;
+
+    let open = app
+        .count(
+            "SELECT COUNT(*) FROM proj_tasks WHERE id = ? AND task_end_date = ''",
+            task_id,
+        )
+        .await;
+    assert_eq!(open, 1);
+    let projects = app
+        .count("SELECT COUNT(*) FROM project WHERE id = ?", proj_id)
+        .await;
+    assert_eq!(projects, 1);
+    let sneaky = app
+        .count("SELECT COUNT(*) FROM project WHERE id <> ?", proj_id)
+        .await;
+    assert_eq!(sneaky, 0);
+}
+
+#[rocket::async_test]
+async fn non_owner_access_is_rejected() {
+    let app = TestApp::new().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Private").await;
+    let task_id = app.add_task(proj_id, "Mine").await;
+    app.logout().await;
+    app.sign_up("mallory").await;
+
+    let response = app.get(format!("/project/{}", proj_id)).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app.get(format!("/edit/project/{}", proj_id)).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app.get(format!("/project/{}/comments", proj_id)).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app
+        .post(
+            format!("/edit/project/{}", proj_id),
+            "name=Pwned&end_date=".to_string(),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    app.post(
+        format!("/project/{}/add-task", proj_id),
+        "description=Spam&priority=2&due_date=".to_string(),
+    )
+    .await;
+    app.get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    app.get(format!("/delete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    app.get(format!("/delete/project/{}", proj_id)).await;
+
+    let name: String = db::query("SELECT name FROM project WHERE id = ?")
+        .bind(proj_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("name");
+    assert_eq!(name, "Private");
+    let tasks = app
+        .count(
+            "SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ? AND task_end_date = ''",
+            proj_id,
+        )
+        .await;
+    assert_eq!(tasks, 1);
+}
+
+#[rocket::async_test]
+async fn board_keeps_completion_in_step() {
+    let app = TestApp::new().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Board").await;
+    let first = app.add_task(proj_id, "First").await;
+    let second = app.add_task(proj_id, "Second").await;
+
+    for task_id in [first, second] {
+        app.get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+            .await;
+    }
+    // completed tasks queue up at the end of the done column
+    let position = "SELECT position FROM proj_tasks WHERE id = ?";
+    assert_eq!(app.count(position, first).await, 0);
+    assert_eq!(app.count(position, second).await, 1);
+
+    app.post(
+        format!("/project/{}/task/{}/move", proj_id, first),
+        "status=todo&position=0".to_string(),
+    )
+    .await;
+    let row = db::query("SELECT status, task_end_date, time_delta FROM proj_tasks WHERE id = ?")
+        .bind(first)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<String, _>("status"), "todo");
+    assert_eq!(row.get::<String, _>("task_end_date"), "");
+    assert_eq!(row.get::<i64, _>("time_delta"), 0);
+    assert_eq!(app.count(position, second).await, 0);
+
+    // reopening and correcting the times move the task between columns as well
+    app.get(format!("/complete/project/{}/task/{}", proj_id, first))
+        .await;
+    app.get(format!("/reopen/project/{}/task/{}", proj_id, second))
+        .await;
+    let status = "SELECT COUNT(*) FROM proj_tasks WHERE id = ? AND status = 'done'";
+    assert_eq!(app.count(status, second).await, 0);
+    assert_eq!(app.count(position, first).await, 0);
+    assert_eq!(
+        app.count("SELECT time_delta FROM proj_tasks WHERE id = ?", second)
+            .await,
+        0
+    );
+    app.post(
+        format!("/edit/project/{}/task/{}/times", proj_id, second),
+        "task_start_date=2024-01-01T09:00&task_end_date=2024-01-01T10:00".to_string(),
+    )
+    .await;
+    assert_eq!(app.count(status, second).await, 1);
+    assert_eq!(app.count(position, second).await, 1);
+    app.post(
+        format!("/edit/project/{}/task/{}/times", proj_id, first),
+        "task_start_date=2024-01-01T09:00&task_end_date=".to_string(),
+    )
+    .await;
+    assert_eq!(app.count(status, first).await, 0);
+    assert_eq!(app.count(position, second).await, 0);
+
+    // completing it again leaves the recorded end alone
+    app.get(format!("/complete/project/{}/task/{}", proj_id, second))
+        .await;
+    let end = "SELECT COUNT(*) FROM proj_tasks WHERE id = ? AND task_end_date LIKE '2024-01-01%'";
+    assert_eq!(app.count(end, second).await, 1);
+}
+
+#[rocket::async_test]
+async fn participants_work_the_board() {
+    let app = TestApp::new().await;
+    app.sign_up("bob").await;
+    app.logout().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Shared").await;
+    let task_id = app.add_task(proj_id, "Together").await;
+    app.post(
+        format!("/project/{}/participant", proj_id),
+        "email=bob@example.com".to_string(),
+    )
+    .await;
+    app.logout().await;
+    app.login("bob@example.com", "hunter2").await;
+
+    // the notification about being added shows with its date
+    let response = app.get("/notifications".to_string()).await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = app.get(format!("/project/{}/board", proj_id)).await;
+    assert_eq!(response.status(), Status::Ok);
+    app.post(
+        format!("/project/{}/board/wip", proj_id),
+        "status=in_progress&wip_limit=1".to_string(),
+    )
+    .await;
+    app.post(
+        format!("/project/{}/task/{}/move", proj_id, task_id),
+        "status=in_progress&position=0".to_string(),
+    )
+    .await;
+    let moved = "SELECT COUNT(*) FROM proj_tasks WHERE id = ? AND status = 'in_progress'";
+    assert_eq!(app.count(moved, task_id).await, 1);
+    let limit =
+        "SELECT wip_limit FROM board_column WHERE owner_proj = ? AND status = 'in_progress'";
+    assert_eq!(app.count(limit, proj_id).await, 1);
+
+    let blocker = app.add_task(proj_id, "First").await;
+    app.post(
+        format!("/project/{}/task/{}/dependency", proj_id, task_id),
+        format!("blocked_by={}", blocker),
+    )
+    .await;
+    let blocked = "SELECT COUNT(*) FROM task_dependency WHERE task_id = ?";
+    assert_eq!(app.count(blocked, task_id).await, 1);
+    let response = app.get(format!("/project/{}/critical-path", proj_id)).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    for page in ["", "/times"] {
+        let uri = format!("/edit/project/{}/task/{}{}", proj_id, task_id, page);
+        assert_eq!(app.get(uri).await.status(), Status::Ok);
+    }
+    // moving the task to another project respects that project's WIP limit
+    let mine = app.add_project("Mine").await;
+    app.post(
+        format!("/project/{}/board/wip", mine),
+        "status=in_progress&wip_limit=0".to_string(),
+    )
+    .await;
+    app.post(
+        format!("/edit/project/{}/task/{}", proj_id, task_id),
+        format!(
+            "description=Together&priority=2&due_date=&owner_proj={}",
+            mine
+        ),
+    )
+    .await;
+    let owner_proj = "SELECT owner_proj FROM proj_tasks WHERE id = ?";
+    assert_eq!(app.count(owner_proj, task_id).await, proj_id);
+}
+
+#[rocket::async_test]
+async fn labels_are_shared_with_the_project() {
+    let app = TestApp::new().await;
+    app.sign_up("bob").await;
+    app.logout().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Shared").await;
+    let task_id = app.add_task(proj_id, "Together").await;
+    app.post(
+        format!("/project/{}/participant", proj_id),
+        "email=bob@example.com".to_string(),
+    )
+    .await;
+    app.post(
+        format!("/project/{}/label", proj_id),
+        "name=Urgent".to_string(),
+    )
+    .await;
+    app.post(
+        format!("/project/{}/task/{}/label", proj_id, task_id),
+        "name=Urgent".to_string(),
+    )
+    .await;
+    app.logout().await;
+    app.login("bob@example.com", "hunter2").await;
+    app.post(
+        format!("/project/{}/label", proj_id),
+        "name=Mine".to_string(),
+    )
+    .await;
+
+    let page = app
+        .get(format!("/project/{}", proj_id))
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(page.contains("Urgent") && page.contains("Mine"));
+
+    // bob takes off his own label but not alice's
+    let labels = db::query("SELECT id, name FROM label")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    for row in labels {
+        let label_id: i64 = row.get("id");
+        app.get(format!("/delete/project/{}/label/{}", proj_id, label_id))
+            .await;
+        app.get(format!(
+            "/delete/project/{}/task/{}/label/{}",
+            proj_id, task_id, label_id
+        ))
+        .await;
+    }
+    let project_labels = "SELECT COUNT(*) FROM project_label WHERE project_id = ?";
+    assert_eq!(app.count(project_labels, proj_id).await, 1);
+    let task_labels = "SELECT COUNT(*) FROM task_label WHERE task_id = ?";
+    assert_eq!(app.count(task_labels, task_id).await, 1);
+}
+
+#[rocket::async_test]
+async fn deleting_a_project_keeps_its_webhooks_history() {
+    let app = TestApp::new().await;
+    let alice = app.sign_up("alice").await;
+    let proj_id = app.add_project("Doomed").await;
+    db::query(
+        "INSERT INTO webhook (owner, project_id, url, secret, events, created)
+        VALUES (?, ?, 'https://example.com/hook', 'secret', 'project.deleted', '')",
+    )
+    .bind(alice)
+    .bind(proj_id)
+    .execute(app.pool())
+    .await
+    .unwrap();
+
+    app.get(format!("/delete/project/{}", proj_id)).await;
+
+    let deleted: bool = db::query("SELECT project_deleted FROM webhook WHERE owner = ?")
+        .bind(alice)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get(0);
+    assert!(deleted);
+    let deliveries = app
+        .count(
+            "SELECT COUNT(*) FROM webhook_delivery d JOIN webhook w ON w.id = d.webhook_id
+            WHERE w.owner = ? AND d.event = 'project.deleted'",
+            alice,
+        )
+        .await;
+    assert_eq!(deliveries, 1);
+}
+
+// answers one request per entry of `statuses`, handing back the headers and body it got
+fn serve(
+    listener: TcpListener,
+    statuses: &'static [&'static str],
+) -> std::thread::JoinHandle<Vec<(String, String)>> {
+    std::thread::spawn(move || {
+        let mut requests = vec![];
+        for status in statuses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line.to_lowercase());
+            }
+            let length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .and_then(|length| length.trim().parse().ok())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            requests.push((headers, String::from_utf8(body).unwrap()));
+        }
+        requests
+    })
+}
+
+#[rocket::async_test]
+async fn webhooks_are_signed_and_retried() {
+    let app = TestApp::new().await;
+    let alice = app.sign_up("alice").await;
+    let proj_id = app.add_project("Hooked").await;
+
+    // local addresses are refused unless webhooks.allow_private_hosts is set
+    app.post(
+        format!("/project/{}/webhooks", proj_id),
+        "url=http://127.0.0.1:9/&secret=topsecret&events=task.created&all_projects=false"
+            .to_string(),
+    )
+    .await;
+    assert_eq!(
+        app.count("SELECT COUNT(*) FROM webhook WHERE owner = ?", alice)
+            .await,
+        0
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = serve(listener, &["500 Internal Server Error", "204 No Content"]);
+    db::query(
+        "INSERT INTO webhook (owner, project_id, url, secret, events, created)
+        VALUES (?, ?, ?, 'topsecret', 'task.created', '')",
+    )
+    .bind(alice)
+    .bind(proj_id)
+    .bind(url)
+    .execute(app.pool())
+    .await
+    .unwrap();
+
+    // the delivery is queued along with the task, nothing runs in the background
+    let task_id = app.add_task(proj_id, "Deliver+me").await;
+    let delivery = "SELECT * FROM webhook_delivery d JOIN webhook w ON w.id = d.webhook_id
+        WHERE w.owner = ?";
+    let row = db::query(delivery)
+        .bind(alice)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    let payload: String = row.get("payload");
+    assert!(payload.contains(&format!("\"task_id\":{}", task_id)));
+
+    let client = reqwest::Client::new();
+    send_due_deliveries(app.pool(), &client).await.unwrap();
+    let row = db::query(delivery)
+        .bind(alice)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<String, _>("status"), "pending");
+    assert_eq!(row.get::<i64, _>("attempts"), 1);
+    assert_eq!(row.get::<Option<i64>, _>("response_code"), Some(500));
+    let next_attempt: String = row.get("next_attempt");
+    let next_attempt = NaiveDateTime::parse_from_str(&next_attempt, db::TIMESTAMP_FORMAT).unwrap();
+    assert!(next_attempt > chrono::Utc::now().naive_utc() + Duration::seconds(25));
+
+    // not due yet, so nothing goes out until the backoff has passed
+    send_due_deliveries(app.pool(), &client).await.unwrap();
+    db::query("UPDATE webhook_delivery SET next_attempt = '2000-01-01 00:00:00'")
+        .execute(app.pool())
+        .await
+        .unwrap();
+    send_due_deliveries(app.pool(), &client).await.unwrap();
+    let row = db::query(delivery)
+        .bind(alice)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<String, _>("status"), "delivered");
+    assert_eq!(row.get::<i64, _>("attempts"), 2);
+    let response = app.get(format!("/project/{}/webhooks", proj_id)).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 2);
+    for (headers, body) in requests {
+        assert_eq!(body, payload);
+        assert!(headers.contains("x-rrs-event: task.created"));
+        let signature = format!("x-rrs-signature: {}", sign("topsecret", body.as_bytes()));
+        assert!(headers.contains(&signature));
+    }
+}
+
+#[rocket::async_test]
+async fn a_bad_mail_config_stops_the_launch() {
+    let db = TestDb::create().await;
+    let rocket = rocket();
+    let figment = rocket
+        .figment()
+        .clone()
+        .merge(("databases.dev-db.url", db.url()))
+        .merge(("mail.transport", "pigeon"));
+    match Client::tracked(rocket.configure(figment)).await {
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::FailedFairings(_))),
+        Ok(_) => panic!("launched with an unknown mail transport"),
+    }
+}
+
+#[rocket::async_test]
+async fn digest_covers_projects_the_user_participates_in() {
+    let app = TestApp::new().await;
+    app.sign_up("bob").await;
+    app.logout().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Shared").await;
+    app.post(
+        format!("/project/{}/participant", proj_id),
+        "email=bob@example.com".to_string(),
+    )
+    .await;
+    let task_id = app.add_task(proj_id, "Ship+it").await;
+    app.get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+        .await;
+
+    let transport = Arc::new(MemoryTransport::default());
+    let mut mailer: Mailer = app.client.rocket().state::<Mailer>().unwrap().clone();
+    mailer.transport = transport.clone();
+    send_digests(app.pool(), &mailer).await.unwrap();
+
+    let sent = transport.0.lock().await;
+    let digest = sent
+        .iter()
+        .find(|email| email.to == "bob@example.com")
+        .expect("bob should get a digest");
+    assert!(digest.html.contains("Shared"));
+    assert!(digest.html.contains("Ship it"));
+}
+
+struct FailingTransport;
+
+#[rocket::async_trait]
+impl Transport for FailingTransport {
+    async fn send(&self, _from: &str, _email: &Email) -> Result<(), String> {
+        Err("connection refused".to_string())
+    }
+}
+
+#[rocket::async_test]
+async fn failed_reminders_are_retried() {
+    let app = TestApp::new().await;
+    let alice = app.sign_up("alice").await;
+    let proj_id = app.add_project("Reminded").await;
+    let task_id = app.add_task(proj_id, "Call+back").await;
+    app.post(
+        "/preferences".to_string(),
+        "email_notifications=false&email_digest=false&reminder_channel=email".to_string(),
+    )
+    .await;
+    app.post(
+        format!("/project/{}/task/{}/reminder", proj_id, task_id),
+        "at=2023-06-01T09:00&unit=minutes".to_string(),
+    )
+    .await;
+    let id: i64 = db::query("SELECT id FROM task_reminder WHERE user_id = ?")
+        .bind(alice)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("id");
+    let reminder = || async {
+        db::query("SELECT status, attempts, retry_at FROM task_reminder WHERE id = ?")
+            .bind(id)
+            .fetch_one(app.pool())
+            .await
+            .unwrap()
+    };
+
+    let mut mailer: Mailer = app.client.rocket().state::<Mailer>().unwrap().clone();
+    mailer.transport = Arc::new(FailingTransport);
+    send_due_reminders(app.pool(), &mailer).await.unwrap();
+    let row = reminder().await;
+    assert_eq!(row.get::<String, _>("status"), "pending");
+    assert_eq!(row.get::<i64, _>("attempts"), 1);
+    assert!(row.get::<Option<String>, _>("retry_at").is_some());
+
+    // not before the delay is up
+    let transport = Arc::new(MemoryTransport::default());
+    mailer.transport = transport.clone();
+    send_due_reminders(app.pool(), &mailer).await.unwrap();
+    assert!(transport.0.lock().await.is_empty());
+
+    db::query("UPDATE task_reminder SET retry_at = '2023-06-01 09:00:00' WHERE id = ?")
+        .bind(id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    send_due_reminders(app.pool(), &mailer).await.unwrap();
+    assert_eq!(reminder().await.get::<String, _>("status"), "sent");
+    assert_eq!(transport.0.lock().await.len(), 1);
+
+    // reminders are only deleted through their own project
+    let other_proj = app.add_project("Other").await;
+    app.get(format!("/delete/project/{}/reminder/{}", other_proj, id))
+        .await;
+    let left = app
+        .count("SELECT COUNT(*) FROM task_reminder WHERE id = ?", id)
+        .await;
+    assert_eq!(left, 1);
+}
+
+// a member of one project naming another project's task in the url
+#[rocket::async_test]
+async fn tasks_from_other_projects_are_rejected() {
+    let app = TestApp::new().await;
+    app.sign_up("alice").await;
+    let proj_id = app.add_project("Private").await;
+    let task_id = app.add_task(proj_id, "Mine").await;
+    app.get(format!("/complete/project/{}/task/{}", proj_id, task_id))
+        .await;
+    app.post(
+        format!("/project/{}/task/{}/checklist", proj_id, task_id),
+        "text=Step+one".to_string(),
+    )
+    .await;
+    let item_id: i64 = db::query("SELECT id FROM checklist_item WHERE task_id = ?")
+        .bind(task_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("id");
+    app.logout().await;
+    let mallory = app.sign_up("mallory").await;
+    let own_proj = app.add_project("Decoy").await;
+
+    app.get(format!("/reopen/project/{}/task/{}", own_proj, task_id))
+        .await;
+    app.post(
+        format!("/project/{}/task/{}/checklist", own_proj, task_id),
+        "text=Spam".to_string(),
+    )
+    .await;
+    app.get(format!(
+        "/toggle/project/{}/task/{}/checklist/{}",
+        own_proj, task_id, item_id
+    ))
+    .await;
+    app.post(
+        format!("/project/{}/task/{}/estimate", own_proj, task_id),
+        "hours=5".to_string(),
+    )
+    .await;
+    app.get(format!("/watch/project/{}/task/{}", own_proj, task_id))
+        .await;
+
+    let row = db::query("SELECT task_end_date, estimate FROM proj_tasks WHERE id = ?")
+        .bind(task_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_ne!(row.get::<String, _>("task_end_date"), "");
+    assert_eq!(row.get::<Option<i64>, _>("estimate"), None);
+    let items = app
+        .count(
+            "SELECT COUNT(*) FROM checklist_item WHERE task_id = ? AND NOT done",
+            task_id,
+        )
+        .await;
+    assert_eq!(items, 1);
+    let watching = app
+        .count("SELECT COUNT(*) FROM task_watch WHERE user_id = ?", mallory)
+        .await;
+    assert_eq!(watching, 0);
+
+    // comments are only edited and deleted through their own project
+    app.post(
+        format!("/project/{}/comment", own_proj),
+        "body=Hello".to_string(),
+    )
+    .await;
+    let comment_id: i64 = db::query("SELECT id FROM comment WHERE author = ?")
+        .bind(mallory)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+        .get("id");
+    app.post(
+        format!("/project/{}/comment/{}/edit", proj_id, comment_id),
+        "body=Changed".to_string(),
+    )
+    .await;
+    app.get(format!(
+        "/delete/project/{}/comment/{}",
+        proj_id, comment_id
+    ))
+    .await;
+    let response = app.get(format!("/project/{}", own_proj)).await;
+    assert_eq!(response.status(), Status::Ok);
+    let row = db::query("SELECT body, deleted FROM comment WHERE id = ?")
+        .bind(comment_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<String, _>("body"), "Hello");
+    assert!(!row.get::<bool, _>("deleted"));
+}
+
+#[rocket::async_test]
+async fn seeding_is_deterministic_and_idempotent() {
+    let config = SeedConfig::default();
+    let expected = SeedSummary {
+        users: config.users,
+        projects: config.projects,
+        tasks: config.projects * config.tasks,
+    };
+
+    let mut listings = vec![];
+    for _ in 0..2 {
+        let app = TestApp::new().await;
+        let mut db = app.pool().acquire().await.unwrap();
+        assert_eq!(
+            seed(&mut db, &config).await.unwrap(),
+            Some(expected.clone())
+        );
+        assert_eq!(seed(&mut db, &config).await.unwrap(), None);
+
+        let rows = db::query(
+            "SELECT p.name, t.description, t.task_start_date, t.time_delta
+            FROM proj_tasks t JOIN project p ON p.id = t.owner_proj
+            ORDER BY t.id",
+        )
+        .fetch_all(&mut db)
+        .await
+        .unwrap();
+        let listing: Vec<(String, String, String, i64)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get("name"),
+                    row.get("description"),
+                    row.get("task_start_date"),
+                    row.get("time_delta"),
+                )
+            })
+            .collect();
+        listings.push(listing);
+    }
+    assert_eq!(listings[0], listings[1]);
+}
@@ -1,21 +1,33 @@
 use crate::auth::hash_password;
+use crate::comment::PROJECT_MEMBER;
+use crate::db::{self, DbConnection, DbPool, DbRow, MIGRATOR};
+use crate::error::{affected, AppError, AppResult};
+use crate::label::{get_project_labels, get_task_labels, Label};
+use crate::live::ProjectEvent;
+use crate::mention::{link_mentions, notify_mentions};
+use crate::notification::{notify, notify_watchers, watch_task};
+use crate::recurrence::start_series;
+use crate::reminder::cancel_reminders;
+use crate::webhook::queue_event;
 use chrono::{Duration, NaiveDateTime, Utc};
 use rocket::fairing::{self, AdHoc};
-use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::serde::{
+    json::{json, Json},
+    Deserialize, Serialize,
+};
 use rocket::{Build, Rocket};
-use rocket_db_pools::{sqlx, sqlx::Row, Connection, Database};
-use sqlx::sqlite::SqliteRow;
+use rocket_db_pools::{sqlx, sqlx::Acquire, sqlx::Row, Connection, Database};
 use std::collections::HashMap;
 
 #[derive(Database, Debug, Clone)]
 #[database("dev-db")]
-pub struct Db(sqlx::SqlitePool);
+pub struct Db(DbPool);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct User {
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
-    pub id: Option<u8>,
+    pub id: Option<i64>,
     pub email: String,
     pub name: String,
     pub password: String,
@@ -23,6 +35,9 @@ pub struct User {
     pub profile_pic: String,
     pub admin: bool,
     pub premium: bool,
+    // unread notifications, counted by user_req_guard for the header bell
+    #[serde(skip_deserializing)]
+    pub unread: i64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -35,12 +50,14 @@ pub struct Admin {
 #[serde(crate = "rocket::serde")]
 pub struct Project {
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
-    pub id: Option<u8>,
+    pub id: Option<i64>,
     pub name: String,
     pub proj_start_date: String,
     pub proj_end_date: String,
-    pub owner: u8,
-    pub participants: Vec<u8>,
+    pub owner: i64,
+    pub participants: Vec<i64>,
+    #[serde(default)]
+    pub labels: Vec<Label>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -51,18 +68,115 @@ pub struct Projects(pub Vec<Project>);
 #[serde(crate = "rocket::serde")]
 pub struct ProjectTask {
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
-    pub id: Option<u8>,
+    pub id: Option<i64>,
     pub description: String,
+    // the escaped description with mentions linked, only filled in by get_all_tasks_for_project
+    #[serde(skip_deserializing)]
+    pub description_html: String,
     pub task_start_date: String,
     pub task_end_date: String,
-    pub owner_proj: u8,
+    pub owner_proj: i64,
     pub time_delta: i64,
+    pub status: String,
+    pub position: i64,
+    pub priority: i64,
+    pub due_date: Option<String>,
+    pub assignee: Option<i64>,
+    pub parent_id: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub overdue: bool,
+    // percentage of checklist items and subtasks that are done, if there are any
+    #[serde(skip_deserializing)]
+    pub progress: Option<i64>,
+    #[serde(default)]
+    pub checklist: Vec<ChecklistItem>,
+    // estimated duration in seconds
+    pub estimate: Option<i64>,
+    // ids of the tasks this one waits on
+    #[serde(default)]
+    pub blocked_by: Vec<i64>,
+    // whether any of `blocked_by` is still open
+    #[serde(skip_deserializing)]
+    pub blocked: bool,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    // ids of the users notified when the task is completed
+    #[serde(default)]
+    pub watchers: Vec<i64>,
+    // the recurring series this task is an instance of
+    pub recurrence_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CriticalPath {
+    pub tasks: Vec<ProjectTask>,
+    // sum of the estimates along the path, in seconds
+    pub duration: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChecklistItem {
+    pub id: i64,
+    pub task_id: i64,
+    pub text: String,
+    pub done: bool,
+    pub position: i64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ProjectTasks(pub Vec<ProjectTask>);
 
+// workflow states a task moves through on the project board, in column order
+pub const TASK_STATUSES: [(&str, &str); 4] = [
+    ("todo", "To do"),
+    ("in_progress", "In progress"),
+    ("review", "Review"),
+    ("done", "Done"),
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BoardColumn {
+    pub status: String,
+    pub label: String,
+    pub wip_limit: Option<i64>,
+    pub tasks: Vec<ProjectTask>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OverdueTask {
+    pub task: ProjectTask,
+    pub project_name: String,
+    // seconds since the task was due
+    pub late_by: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TaskTimeLog {
+    pub id: i64,
+    pub task_start_date: String,
+    pub task_end_date: String,
+    pub time_delta: i64,
+    // why the times were replaced: "reopened" or "corrected"
+    pub reason: String,
+    pub recorded: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TaskTimes {
+    pub task: ProjectTask,
+    // datepicker values for the task's start and end dates
+    pub start_input: String,
+    pub end_input: String,
+    pub history: Vec<TaskTimeLog>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ProjectWithTasks {
@@ -70,7 +184,7 @@ pub struct ProjectWithTasks {
     pub tasks: Option<ProjectTasks>,
 }
 
-fn serilaize_user(r: SqliteRow) -> Json<User> {
+fn serilaize_user(r: DbRow) -> Json<User> {
     Json(User {
         id: Some(r.get(0)),
         email: r.get(1),
@@ -80,333 +194,1659 @@ fn serilaize_user(r: SqliteRow) -> Json<User> {
         profile_pic: r.get(5),
         admin: r.get(6),
         premium: r.get(7),
+        unread: 0,
     })
 }
 
+fn serialize_task(row: &DbRow) -> ProjectTask {
+    ProjectTask {
+        id: row.get::<Option<i64>, _>("id"),
+        description: row.get("description"),
+        description_html: String::new(),
+        task_start_date: row.get("task_start_date"),
+        task_end_date: row.get("task_end_date"),
+        owner_proj: row.get("owner_proj"),
+        time_delta: row.get("time_delta"),
+        status: row.get("status"),
+        position: row.get("position"),
+        priority: row.get("priority"),
+        due_date: row.get("due_date"),
+        assignee: row.get("assignee"),
+        parent_id: row.get("parent_id"),
+        recurrence_id: row.get("recurrence_id"),
+        overdue: is_overdue(row.get("due_date"), row.get("task_end_date")),
+        // only present when the query counts the task's children, see TASK_PROGRESS_COLUMNS
+        progress: match (
+            row.try_get::<i64, _>("children"),
+            row.try_get::<i64, _>("children_done"),
+        ) {
+            (Ok(children), Ok(children_done)) if children > 0 => {
+                Some(children_done * 100 / children)
+            }
+            _ => None,
+        },
+        checklist: vec![],
+        estimate: row.get("estimate"),
+        blocked_by: vec![],
+        blocked: false,
+        labels: vec![],
+        watchers: vec![],
+    }
+}
+
+fn serialize_checklist_item(row: &DbRow) -> ChecklistItem {
+    ChecklistItem {
+        id: row.get("id"),
+        task_id: row.get("task_id"),
+        text: row.get("text"),
+        done: row.get("done"),
+        position: row.get("position"),
+    }
+}
+
+// counts of a task's checklist items and subtasks, for the progress of `t`
+const TASK_PROGRESS_COLUMNS: &str = "
+    (SELECT COUNT(*) FROM checklist_item c WHERE c.task_id = t.id)
+        + (SELECT COUNT(*) FROM proj_tasks s WHERE s.parent_id = t.id) AS children,
+    (SELECT COUNT(*) FROM checklist_item c WHERE c.task_id = t.id AND c.done)
+        + (SELECT COUNT(*) FROM proj_tasks s WHERE s.parent_id = t.id AND s.task_end_date <> '')
+        AS children_done";
+
+// the ids of a task and all of its subtasks, however deeply nested
+const TASK_TREE_CTE: &str = "
+    WITH RECURSIVE tree(id) AS (
+        SELECT ?
+        UNION ALL
+        SELECT t.id FROM proj_tasks t JOIN tree ON t.parent_id = tree.id
+    )";
+
+// a task is overdue once its due date has passed without it being completed
+fn is_overdue(due_date: Option<String>, task_end_date: String) -> bool {
+    match due_date {
+        Some(due_date) if task_end_date.is_empty() => {
+            match NaiveDateTime::parse_from_str(due_date.as_str(), db::TIMESTAMP_FORMAT) {
+                Ok(due) => due < Utc::now().naive_utc(),
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
 pub struct CompleteTask(pub ());
 
-pub async fn get_user_by_id(mut db: Connection<Db>, id: u8) -> Option<Json<User>> {
-    let result = sqlx::query(
-        "SELECT id, email, name, password, created, profile_pic, admin, premium FROM user WHERE id = ?",
+pub async fn get_user_by_id(mut db: Connection<Db>, id: i64) -> AppResult<Json<User>> {
+    let row = db::query(
+        "SELECT id, email, name, password, created, profile_pic, admin, premium FROM \"user\" WHERE id = ?",
     )
     .bind(id)
-    .fetch_one(&mut *db)
-    .await;
-    match result {
-        Ok(r) => Some(serilaize_user(r)),
-        Err(_) => None,
-    }
+    .fetch_optional(&mut *db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    Ok(serilaize_user(row))
 }
 
-pub async fn get_user_by_email(mut db: Connection<Db>, email: &str) -> Option<Json<User>> {
-    let result = sqlx::query(
-        "SELECT id, email, name, password, created, profile_pic, admin, premium FROM user WHERE email = ?",
+pub async fn get_user_by_email(mut db: Connection<Db>, email: &str) -> AppResult<Json<User>> {
+    let row = db::query(
+        "SELECT id, email, name, password, created, profile_pic, admin, premium FROM \"user\" WHERE email = ?",
     )
     .bind(email)
-    .fetch_one(&mut *db)
-    .await;
-    match result {
-        Ok(r) => Some(serilaize_user(r)),
-        Err(_) => None,
-    }
+    .fetch_optional(&mut *db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("No user with that email".to_string()))?;
+
+    Ok(serilaize_user(row))
 }
 
-pub async fn user_req_guard(mut db: Connection<Db>, id: u8) -> Option<User> {
-    let result = sqlx::query(
-        "SELECT id, email, name, password, created, profile_pic, admin, premium FROM user WHERE id = ?",
+pub async fn user_req_guard(mut db: Connection<Db>, id: i64) -> AppResult<User> {
+    let r = db::query(
+        "SELECT id, email, name, password, created, profile_pic, admin, premium,
+            (SELECT COUNT(*) FROM notification n WHERE n.user_id = \"user\".id AND NOT n.read)
+        FROM \"user\" WHERE id = ?",
     )
     .bind(id)
     .fetch_one(&mut *db)
-    .await;
-    match result {
-        Ok(r) => Some(User {
-            id: Some(r.get(0)),
-            email: r.get(1),
-            name: r.get(2),
-            password: r.get(3),
-            created: r.get(4),
-            profile_pic: r.get(5),
-            admin: r.get(6),
-            premium: r.get(7),
-        }),
-        Err(_) => None,
-    }
-}
-
-pub async fn get_all_projects_for_user(
-    mut db: Connection<Db>,
-    id: u8,
-) -> Result<Vec<Project>, String> {
-    let result = sqlx::query("SELECT * FROM project WHERE owner = ? ORDER BY proj_start_date DESC")
+    .await?;
+
+    Ok(User {
+        id: Some(r.get(0)),
+        email: r.get(1),
+        name: r.get(2),
+        password: r.get(3),
+        created: r.get(4),
+        profile_pic: r.get(5),
+        admin: r.get(6),
+        premium: r.get(7),
+        unread: r.get(8),
+    })
+}
+
+fn serialize_project(row: &DbRow) -> Project {
+    Project {
+        id: row.get::<Option<i64>, _>("id"),
+        name: row.get("name"),
+        proj_start_date: row.get("proj_start_date"),
+        proj_end_date: row.get("proj_end_date"),
+        owner: row.get("owner"),
+        // Assuming participants is stored as a comma-separated string of i64 values
+        participants: row
+            .get::<String, _>("participants")
+            .split(',')
+            .filter_map(|s| s.parse::<i64>().ok())
+            .collect(),
+        labels: vec![],
+    }
+}
+
+pub async fn get_all_projects_for_user(mut db: Connection<Db>, id: i64) -> AppResult<Vec<Project>> {
+    let rows = db::query("SELECT * FROM project WHERE owner = ? ORDER BY proj_start_date DESC")
         .bind(id)
         .fetch_all(&mut *db)
-        .await;
-    match result {
-        Ok(rows) => {
-            let projects: Vec<Project> = rows
-                .into_iter()
-                .map(|row| {
-                    Project {
-                        id: row.get::<Option<u8>, _>("id"),
-                        name: row.get("name"),
-                        proj_start_date: row.get("proj_start_date"),
-                        proj_end_date: row.get("proj_end_date"),
-                        owner: row.get("owner"),
-                        // Assuming participants is stored as a comma-separated string of u8 values
-                        participants: row
-                            .get::<String, _>("participants")
-                            .split(',')
-                            .filter_map(|s| s.parse::<u8>().ok())
-                            .collect(),
-                    }
-                })
-                .collect();
-            Ok(projects)
-        }
-        Err(e) => Err(format!("Failed to get projects: {}", e)),
-    }
+        .await?;
+    Ok(rows.iter().map(serialize_project).collect())
+}
+
+// the projects `id` owns or participates in
+pub async fn get_member_projects(db: &mut DbConnection, id: i64) -> AppResult<Vec<Project>> {
+    let rows = db::query(
+        "SELECT * FROM project
+        WHERE owner = ? OR (',' || participants || ',') LIKE ('%,' || ? || ',%')
+        ORDER BY proj_start_date DESC",
+    )
+    .bind(id)
+    .bind(id)
+    .fetch_all(db)
+    .await?;
+    Ok(rows.iter().map(serialize_project).collect())
 }
 
 pub async fn get_all_tasks_for_project(
     mut db: Connection<Db>,
-    proj_id: u8,
-) -> Result<Vec<ProjectTask>, String> {
-    let result = sqlx::query("SELECT * FROM proj_tasks WHERE owner_proj = ?")
-        .bind(proj_id)
-        .fetch_all(&mut *db)
-        .await;
-    match result {
-        Ok(rows) => {
-            let tasks: Vec<ProjectTask> = rows
-                .into_iter()
-                .map(|row| ProjectTask {
-                    id: row.get::<Option<u8>, _>("id"),
-                    description: row.get("description"),
-                    task_start_date: row.get("task_start_date"),
-                    task_end_date: row.get("task_end_date"),
-                    owner_proj: row.get("owner_proj"),
-                    time_delta: row.get("time_delta"),
-                })
-                .collect();
-            Ok(tasks)
-        }
-        Err(e) => Err(format!("Failed to get tasks: {}", e)),
+    proj_id: i64,
+) -> AppResult<Vec<ProjectTask>> {
+    let rows = db::query(&format!(
+        "SELECT t.*, {} FROM proj_tasks t WHERE t.owner_proj = ?",
+        TASK_PROGRESS_COLUMNS
+    ))
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await?;
+    let checklist: Vec<ChecklistItem> = db::query(
+        "SELECT c.* FROM checklist_item c
+        JOIN proj_tasks t ON t.id = c.task_id
+        WHERE t.owner_proj = ?
+        ORDER BY c.position, c.id",
+    )
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(serialize_checklist_item)
+    .collect();
+    let dependencies: Vec<(i64, i64)> = db::query(
+        "SELECT d.task_id, d.blocked_by FROM task_dependency d
+        JOIN proj_tasks t ON t.id = d.task_id
+        WHERE t.owner_proj = ?",
+    )
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(|row| (row.get("task_id"), row.get("blocked_by")))
+    .collect();
+
+    let labels = get_task_labels(&mut db, proj_id).await?;
+    let watchers: Vec<(i64, i64)> = db::query(
+        "SELECT w.task_id, w.user_id FROM task_watch w
+        JOIN proj_tasks t ON t.id = w.task_id
+        WHERE t.owner_proj = ?",
+    )
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(|row| (row.get("task_id"), row.get("user_id")))
+    .collect();
+    let members = get_project_members(&mut db, proj_id).await?;
+
+    let mut tasks: Vec<ProjectTask> = rows.iter().map(serialize_task).collect();
+    let open: Vec<i64> = tasks
+        .iter()
+        .filter(|task| task.task_end_date.is_empty())
+        .filter_map(|task| task.id)
+        .collect();
+    for task in tasks.iter_mut() {
+        task.checklist = checklist
+            .iter()
+            .filter(|item| Some(item.task_id) == task.id)
+            .cloned()
+            .collect();
+        task.blocked_by = dependencies
+            .iter()
+            .filter(|(task_id, _)| Some(*task_id) == task.id)
+            .map(|(_, blocked_by)| *blocked_by)
+            .collect();
+        task.blocked = task.blocked_by.iter().any(|id| open.contains(id));
+        task.labels = labels
+            .iter()
+            .filter(|(task_id, _)| Some(*task_id) == task.id)
+            .map(|(_, label)| label.clone())
+            .collect();
+        task.watchers = watchers
+            .iter()
+            .filter(|(task_id, _)| Some(*task_id) == task.id)
+            .map(|(_, user_id)| *user_id)
+            .collect();
+        task.description_html = link_mentions(&tera::escape_html(&task.description), &members);
     }
+
+    Ok(tasks)
 }
 
+// with a `label`, only projects carrying it or holding a task that carries it are returned
 pub async fn get_all_projects_and_tasks_for_user(
     mut db: Connection<Db>,
-    id: u8,
-) -> Result<Vec<ProjectWithTasks>, String> {
-    let result = sqlx::query(
+    id: i64,
+    label: Option<i64>,
+) -> AppResult<Vec<ProjectWithTasks>> {
+    let rows = db::query(
         "
-        SELECT p.*, t.id AS task_id, t.description, t.task_start_date, t.task_end_date, t.owner_proj, t.time_delta
+        SELECT p.*, t.id AS task_id, t.description, t.task_start_date, t.task_end_date, t.owner_proj, t.time_delta, t.status, t.position, t.priority, t.due_date, t.assignee, t.parent_id, t.estimate, t.recurrence_id
     FROM project p
     LEFT JOIN (
         SELECT *,
         ROW_NUMBER() OVER (PARTITION BY owner_proj ORDER BY task_start_date DESC) AS row_num
         FROM proj_tasks
     ) t ON p.id = t.owner_proj AND t.row_num <= 3
-    WHERE p.owner = ?
+    WHERE p.owner = ?1
+    AND (
+        ?2 IS NULL
+        OR p.id IN (SELECT project_id FROM project_label WHERE label_id = ?2)
+        OR p.id IN (
+            SELECT lt.owner_proj FROM proj_tasks lt
+            JOIN task_label tl ON tl.task_id = lt.id
+            WHERE tl.label_id = ?2
+        )
+    )
     ORDER BY p.proj_start_date DESC, t.task_start_date DESC",
     )
     .bind(id)
+    .bind(label)
     .fetch_all(&mut *db)
-    .await;
-    match result {
-        Ok(rows) => {
-            let mut project_task_map: HashMap<u8, (Project, Vec<ProjectTask>)> = HashMap::new();
-
-            for row in rows {
-                let project_id = row.get::<u8, _>("id");
-                let project = Project {
-                    id: Some(project_id),
-                    name: row.get("name"),
-                    proj_start_date: row.get("proj_start_date"),
-                    proj_end_date: row.get("proj_end_date"),
-                    owner: row.get("owner"),
-                    // Assuming participants is stored as a comma-separated string of u8 values
-                    participants: row
-                        .get::<String, _>("participants")
-                        .split(',')
-                        .filter_map(|s| s.parse::<u8>().ok())
-                        .collect(),
-                };
-
-                let task_id: Option<u8> = row.get("task_id");
-                if let Some(task_id) = task_id {
-                    let task = ProjectTask {
-                        id: Some(task_id),
-                        description: row.get("description"),
-                        task_start_date: row.get("task_start_date"),
-                        task_end_date: row.get("task_end_date"),
-                        owner_proj: row.get("owner_proj"),
-                        time_delta: row.get("time_delta"),
-                    };
-
-                    let entry = project_task_map
-                        .entry(project_id)
-                        .or_insert((project, vec![]));
-                    entry.1.push(task);
-                } else {
-                    project_task_map
-                        .entry(project_id)
-                        .or_insert((project, vec![]));
-                }
-            }
+    .await?;
+    let labels = get_project_labels(&mut db, id).await?;
+    let mut project_task_map: HashMap<i64, (Project, Vec<ProjectTask>)> = HashMap::new();
 
-            let projects_with_tasks: Vec<ProjectWithTasks> = project_task_map
-                .into_iter()
-                .map(|(_, (project, tasks))| ProjectWithTasks {
-                    project,
-                    tasks: if tasks.is_empty() {
-                        None
-                    } else {
-                        Some(ProjectTasks(tasks))
-                    },
-                })
-                .collect();
+    for row in rows {
+        let project_id = row.get::<i64, _>("id");
+        let project = Project {
+            id: Some(project_id),
+            name: row.get("name"),
+            proj_start_date: row.get("proj_start_date"),
+            proj_end_date: row.get("proj_end_date"),
+            owner: row.get("owner"),
+            // Assuming participants is stored as a comma-separated string of i64 values
+            participants: row
+                .get::<String, _>("participants")
+                .split(',')
+                .filter_map(|s| s.parse::<i64>().ok())
+                .collect(),
+            labels: vec![],
+        };
 
-            Ok(projects_with_tasks)
+        let task_id: Option<i64> = row.get("task_id");
+        if let Some(task_id) = task_id {
+            let task = ProjectTask {
+                id: Some(task_id),
+                description: row.get("description"),
+                description_html: String::new(),
+                task_start_date: row.get("task_start_date"),
+                task_end_date: row.get("task_end_date"),
+                owner_proj: row.get("owner_proj"),
+                time_delta: row.get("time_delta"),
+                status: row.get("status"),
+                position: row.get("position"),
+                priority: row.get("priority"),
+                due_date: row.get("due_date"),
+                assignee: row.get("assignee"),
+                parent_id: row.get("parent_id"),
+                recurrence_id: row.get("recurrence_id"),
+                overdue: is_overdue(row.get("due_date"), row.get("task_end_date")),
+                progress: None,
+                checklist: vec![],
+                estimate: row.get("estimate"),
+                blocked_by: vec![],
+                blocked: false,
+                labels: vec![],
+                watchers: vec![],
+            };
+
+            let entry = project_task_map
+                .entry(project_id)
+                .or_insert((project, vec![]));
+            entry.1.push(task);
+        } else {
+            project_task_map
+                .entry(project_id)
+                .or_insert((project, vec![]));
         }
-        Err(e) => Err(format!("Failed to get projects: {}", e)),
     }
+
+    let projects_with_tasks: Vec<ProjectWithTasks> = project_task_map
+        .into_iter()
+        .map(|(_, (mut project, tasks))| ProjectWithTasks {
+            project: {
+                project.labels = labels
+                    .iter()
+                    .filter(|(project_id, _)| Some(*project_id) == project.id)
+                    .map(|(_, label)| label.clone())
+                    .collect();
+                project
+            },
+            tasks: if tasks.is_empty() {
+                None
+            } else {
+                Some(ProjectTasks(tasks))
+            },
+        })
+        .collect();
+
+    Ok(projects_with_tasks)
 }
 
-pub async fn get_project_by_id(mut db: Connection<Db>, id: u8) -> Result<Project, ()> {
-    let result = sqlx::query("SELECT * FROM project WHERE id = ?")
-        .bind(id)
-        .fetch_one(&mut *db)
-        .await;
-
-    match result {
-        Ok(row) => Ok({
-            Project {
-                id: row.get::<Option<u8>, _>("id"),
-                name: row.get("name"),
-                proj_start_date: row.get("proj_start_date"),
-                proj_end_date: row.get("proj_end_date"),
-                owner: row.get("owner"),
-                // assuming participants is stored as a comma-separated string of u8 values
-                participants: row
-                    .get::<String, _>("participants")
-                    .split(',')
-                    .filter_map(|s| s.parse::<u8>().ok())
-                    .collect(),
-            }
-        }),
-        Err(e) => {
-            error!("Failed to get project: {}", e);
-            Err(())
-        }
+// owners and participants get in, and the task, when given, has to belong to the project
+pub async fn require_member(
+    db: &mut DbConnection,
+    user_id: i64,
+    proj_id: i64,
+    task_id: Option<i64>,
+) -> AppResult<()> {
+    let allowed: bool = db::query(&format!(
+        "SELECT {}
+        AND (?4 IS NULL OR EXISTS (SELECT 1 FROM proj_tasks WHERE id = ?4 AND owner_proj = ?1))",
+        PROJECT_MEMBER
+    ))
+    .bind(proj_id)
+    .bind(user_id)
+    .bind(user_id)
+    .bind(task_id)
+    .fetch_one(db)
+    .await?
+    .get(0);
+
+    match allowed {
+        true => Ok(()),
+        false => Err(AppError::Forbidden(
+            "You're not a member of this project".to_string(),
+        )),
     }
 }
 
-pub async fn add_user(mut db: Connection<Db>, name: &str, email: &str, password: &str) {
-    let created = Utc::now().to_string();
-    let password = hash_password(password);
-    let result = sqlx::query!(
-        "INSERT INTO user (name, email, password, created) VALUES (?, ?, ?, ?)",
-        name,
-        email,
-        password,
-        created
-    )
-    .execute(&mut *db)
-    .await;
-    match result {
-        Ok(_) => println!("User added successfully"),
-        Err(e) => error!("Failed to add user: {}", e),
+pub async fn require_owner(db: &mut DbConnection, user_id: i64, proj_id: i64) -> AppResult<()> {
+    let owned = db::query("SELECT 1 FROM project WHERE id = ? AND owner = ?")
+        .bind(proj_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+
+    match owned {
+        Some(_) => Ok(()),
+        None => Err(AppError::Forbidden(
+            "Only the project owner can do that".to_string(),
+        )),
     }
 }
 
-pub async fn add_project(mut db: Connection<Db>, name: &str, id: u8) -> u8 {
-    let proj_start_date = Utc::now().to_string();
-    let result = sqlx::query!(
-        "INSERT INTO project (name, proj_start_date, owner) VALUES (?, ?, ?)",
-        name,
-        proj_start_date,
-        id,
+pub async fn get_project_by_id(mut db: Connection<Db>, id: i64) -> AppResult<Project> {
+    let row = db::query("SELECT * FROM project WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Project not found".to_string()))?;
+
+    let mut project = Project {
+        id: row.get::<Option<i64>, _>("id"),
+        name: row.get("name"),
+        proj_start_date: row.get("proj_start_date"),
+        proj_end_date: row.get("proj_end_date"),
+        owner: row.get("owner"),
+        // assuming participants is stored as a comma-separated string of i64 values
+        participants: row
+            .get::<String, _>("participants")
+            .split(',')
+            .filter_map(|s| s.parse::<i64>().ok())
+            .collect(),
+        labels: vec![],
+    };
+    project.labels = get_project_labels(&mut db, project.owner)
+        .await?
+        .into_iter()
+        .filter(|(project_id, _)| Some(*project_id) == project.id)
+        .map(|(_, label)| label)
+        .collect();
+    Ok(project)
+}
+
+pub async fn add_user(
+    db: &mut DbConnection,
+    name: &str,
+    email: &str,
+    password: &str,
+) -> AppResult<i64> {
+    if name.trim().is_empty() || email.trim().is_empty() || password.is_empty() {
+        return Err(AppError::Validation(
+            "Name, email and password are required".to_string(),
+        ));
+    }
+    let created = db::now();
+    let password = hash_password(password);
+    let user_id: i64 = db::query(
+        "INSERT INTO \"user\" (name, email, password, created) VALUES (?, ?, ?, ?) RETURNING id",
     )
-    .execute(&mut *db)
-    .await;
-    match &result {
-        Ok(_) => println!("Project added successfully"),
-        Err(e) => error!("Failed to add project: {}", e),
+    .bind(name)
+    .bind(email)
+    .bind(password)
+    .bind(created)
+    .fetch_one(db)
+    .await
+    .map_err(|e| match AppError::from(e) {
+        AppError::Conflict(_) => {
+            AppError::Conflict("An account with that email already exists".to_string())
+        }
+        e => e,
+    })?
+    .get("id");
+
+    info!("user {} signed up", user_id);
+    Ok(user_id)
+}
+
+// the functions changing projects and tasks queue the webhook deliveries for it in
+// their transaction and return the events for the handler to publish
+pub async fn add_project(mut db: Connection<Db>, name: &str, id: i64) -> AppResult<ProjectEvent> {
+    if name.trim().is_empty() {
+        return Err(AppError::Validation("A project needs a name".to_string()));
     }
+    let proj_start_date = db::now();
+    let mut tx = (&mut *db).begin().await?;
+    let project_id: i64 = db::query(
+        "INSERT INTO project (name, proj_start_date, owner) VALUES (?, ?, ?) RETURNING id",
+    )
+    .bind(name)
+    .bind(proj_start_date)
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await?
+    .get("id");
+    let event = ProjectEvent::new(
+        project_id,
+        "project.created",
+        None,
+        json!({"name": name, "owner": id}),
+    );
+    queue_event(&mut tx, &event).await?;
+    tx.commit().await?;
+    info!("project {} added by user {}", project_id, id);
 
-    result.unwrap().last_insert_rowid() as u8
+    Ok(event)
 }
 
-pub async fn add_task(mut db: Connection<Db>, description: &str, owner_proj: u8) -> u8 {
-    let task_start_date = Utc::now().to_string();
-    let result = sqlx::query!(
-        "INSERT INTO proj_tasks (description, task_start_date, owner_proj) VALUES (?, ?, ?)",
-        description,
-        task_start_date,
-        owner_proj,
+#[allow(clippy::too_many_arguments)]
+pub async fn add_task(
+    mut db: Connection<Db>,
+    author: i64,
+    description: &str,
+    owner_proj: i64,
+    priority: i64,
+    due_date: Option<String>,
+    parent_id: Option<i64>,
+    rrule: Option<&str>,
+) -> AppResult<ProjectEvent> {
+    if description.trim().is_empty() {
+        return Err(AppError::Validation(
+            "A task needs a description".to_string(),
+        ));
+    }
+    if let Some(parent_id) = parent_id {
+        db::query("SELECT 1 FROM proj_tasks WHERE id = ? AND owner_proj = ?")
+            .bind(parent_id)
+            .bind(owner_proj)
+            .fetch_optional(&mut *db)
+            .await?
+            .ok_or_else(|| {
+                AppError::NotFound("The parent task must belong to the project".to_string())
+            })?;
+    }
+    let task_start_date = db::now();
+    let mut tx = (&mut *db).begin().await?;
+    let task_id: i64 = db::query(
+        "INSERT INTO proj_tasks (description, task_start_date, owner_proj, position, priority, due_date, parent_id)
+        VALUES (?, ?, ?, (SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ? AND status = 'todo'), ?, ?, ?)
+        RETURNING id",
     )
-    .execute(&mut *db)
-    .await;
-    match &result {
-        Ok(_) => println!("Task added successfully"),
-        Err(e) => error!("Failed to add task: {}", e),
+    .bind(description)
+    .bind(task_start_date)
+    .bind(owner_proj)
+    .bind(owner_proj)
+    .bind(priority)
+    .bind(due_date)
+    .bind(parent_id)
+    .fetch_one(&mut tx)
+    .await?
+    .get("id");
+    let event = ProjectEvent::new(
+        owner_proj,
+        "task.created",
+        Some(task_id),
+        json!({"description": description, "priority": priority, "parent_id": parent_id}),
+    );
+    queue_event(&mut tx, &event).await?;
+    tx.commit().await?;
+    info!("task {} added to project {}", task_id, owner_proj);
+
+    if let Some(rrule) = rrule {
+        if let Err(e) = start_series(&mut db, task_id, rrule).await {
+            error!("Failed to start recurring task: {}", e);
+        }
+    }
+
+    let what = format!("task \"{}\"", description);
+    let link = format!("/project/{}", owner_proj);
+    let notified = match get_project_members(&mut db, owner_proj).await {
+        Ok(members) => {
+            notify_mentions(&mut db, &members, author, description, "", &what, &link).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = notified {
+        error!("Failed to notify mentions: {}", e);
+    }
+    // whoever creates a task hears about it being completed
+    if let Err(e) = watch_task(&mut db, task_id, author).await {
+        error!("Failed to watch task: {}", e);
     }
 
-    result.unwrap().last_insert_rowid() as u8
+    Ok(event)
 }
 
 // edit_project(db, id, form_data.name, form_data.end_date)
-pub async fn edit_project(mut db: Connection<Db>, id: u8, name: &str, proj_end_date: &str) -> u8 {
+pub async fn edit_project(
+    mut db: Connection<Db>,
+    id: i64,
+    name: &str,
+    proj_end_date: &str,
+) -> AppResult<ProjectEvent> {
     // let proj_end_date = parse_date(proj_end_date);
-    let result = sqlx::query!(
+    let mut tx = (&mut *db).begin().await?;
+    let result = db::query(
         "UPDATE project
         SET name = ?, proj_end_date = ?
-        WHERE id = ?;",
-        name,
-        proj_end_date,
+        WHERE id = ?",
+    )
+    .bind(name)
+    .bind(proj_end_date)
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+    affected(result.rows_affected(), "Project")?;
+    let event = ProjectEvent::new(
         id,
+        "project.updated",
+        None,
+        json!({"name": name, "proj_end_date": proj_end_date}),
+    );
+    queue_event(&mut tx, &event).await?;
+    tx.commit().await?;
+    info!("project {} edited", id);
+
+    Ok(event)
+}
+
+// the project owner plus everyone listed in its participants
+pub async fn get_project_members(
+    db: &mut DbConnection,
+    proj_id: i64,
+) -> Result<Vec<User>, sqlx::Error> {
+    let rows = db::query(
+        "
+        SELECT u.id, u.email, u.name, u.password, u.created, u.profile_pic, u.admin, u.premium
+        FROM \"user\" u, project p
+        WHERE p.id = ?
+        AND (u.id = p.owner OR (',' || p.participants || ',') LIKE ('%,' || u.id || ',%'))
+        ORDER BY u.name",
     )
-    .execute(&mut *db)
-    .await;
-    match &result {
-        Ok(_) => println!("Project edited successfully"),
-        Err(e) => error!("Failed to edit project: {}", e),
+    .bind(proj_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| serilaize_user(row).0).collect())
+}
+
+pub async fn get_task_and_members(
+    mut db: Connection<Db>,
+    id: i64,
+) -> AppResult<(ProjectTask, Vec<User>)> {
+    let row = db::query("SELECT * FROM proj_tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
+    let task = serialize_task(&row);
+    let members = get_project_members(&mut db, task.owner_proj).await?;
+
+    Ok((task, members))
+}
+
+// updates the editable fields of a task; when `owner_proj` differs from the task's
+// current project the task is moved to the end of the same column in that project
+#[allow(clippy::too_many_arguments)]
+pub async fn edit_task_db(
+    mut db: Connection<Db>,
+    author: i64,
+    id: i64,
+    description: &str,
+    priority: i64,
+    due_date: Option<String>,
+    assignee: Option<i64>,
+    owner_proj: i64,
+) -> AppResult<()> {
+    if description.trim().is_empty() {
+        return Err(AppError::Validation(
+            "A task needs a description".to_string(),
+        ));
+    }
+    let mut tx = (&mut *db).begin().await?;
+
+    let row = db::query(
+        "SELECT owner_proj, status, position, description, assignee FROM proj_tasks WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
+    let old_proj: i64 = row.get("owner_proj");
+    let status: String = row.get("status");
+    let mut position: i64 = row.get("position");
+    let previous: String = row.get("description");
+    let old_assignee: Option<i64> = row.get("assignee");
+    if old_proj != owner_proj {
+        require_member(&mut tx, author, owner_proj, None).await?;
+    }
+
+    let members = get_project_members(&mut tx, owner_proj).await?;
+    if let Some(assignee) = assignee {
+        if !members.iter().any(|member| member.id == Some(assignee)) {
+            return Err(AppError::Validation(
+                "The assignee is not a member of the project".to_string(),
+            ));
+        }
+    }
+
+    if old_proj != owner_proj {
+        db::query(
+            "UPDATE proj_tasks SET position = position - 1
+            WHERE owner_proj = ? AND status = ? AND position > ?",
+        )
+        .bind(old_proj)
+        .bind(&status)
+        .bind(position)
+        .execute(&mut tx)
+        .await?;
+
+        // subtasks follow their parent, and a subtask moved on its own leaves its parent behind
+        db::query(&format!(
+            "{} UPDATE proj_tasks SET owner_proj = ? WHERE id IN (SELECT id FROM tree) AND id <> ?",
+            TASK_TREE_CTE
+        ))
+        .bind(id)
+        .bind(owner_proj)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        db::query("UPDATE proj_tasks SET parent_id = NULL WHERE id = ?")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+        position = db::query("SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ? AND status = ?")
+            .bind(owner_proj)
+            .bind(&status)
+            .fetch_one(&mut tx)
+            .await?
+            .get(0);
+        check_wip_limit(&mut tx, owner_proj, &status, position).await?;
     }
-    // TODO: this is not returning the id of the edited project
-    result.unwrap().last_insert_rowid() as u8
+
+    db::query(
+        "UPDATE proj_tasks
+        SET description = ?, priority = ?, due_date = ?, assignee = ?, owner_proj = ?, position = ?
+        WHERE id = ?",
+    )
+    .bind(description)
+    .bind(priority)
+    .bind(due_date)
+    .bind(assignee)
+    .bind(owner_proj)
+    .bind(position)
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+
+    // a task moved to another project mentions its new members for the first time
+    let previous = if old_proj == owner_proj {
+        &previous
+    } else {
+        ""
+    };
+    let what = format!("task \"{}\"", description);
+    let link = format!("/project/{}", owner_proj);
+    notify_mentions(
+        &mut tx,
+        &members,
+        author,
+        description,
+        previous,
+        &what,
+        &link,
+    )
+    .await?;
+
+    // the new assignee starts watching the task
+    if let Some(assignee) = assignee.filter(|assignee| Some(*assignee) != old_assignee) {
+        watch_task(&mut tx, id, assignee).await?;
+        if assignee != author {
+            let message = format!("You were assigned to {}", what);
+            notify(&mut tx, assignee, "assignment", &message, &link).await?;
+        }
+    }
+
+    Ok(tx.commit().await?)
 }
 
-pub async fn delete_project_db(mut db: Connection<Db>, id: u8) -> Result<Option<()>, sqlx::Error> {
-    let result = sqlx::query!("DELETE FROM project WHERE id = ?", id)
-        .execute(&mut *db)
+pub async fn get_overdue_tasks_for_user(
+    mut db: Connection<Db>,
+    id: i64,
+    label: Option<i64>,
+) -> AppResult<Vec<OverdueTask>> {
+    let rows = db::query(&format!(
+        "
+        SELECT t.*, p.name AS project_name,
+            {} - {} AS late_by
+        FROM proj_tasks t
+        JOIN project p ON p.id = t.owner_proj
+        WHERE p.owner = ?1
+        AND t.due_date IS NOT NULL
+        AND t.due_date < {}
+        AND (t.task_end_date IS NULL OR t.task_end_date = '')
+        AND (
+            ?2 IS NULL
+            OR t.id IN (SELECT task_id FROM task_label WHERE label_id = ?2)
+            OR p.id IN (SELECT project_id FROM project_label WHERE label_id = ?2)
+        )
+        ORDER BY t.due_date ASC",
+        db::epoch_seconds(db::NOW),
+        db::epoch_seconds("t.due_date"),
+        db::NOW
+    ))
+    .bind(id)
+    .bind(label)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| OverdueTask {
+            task: serialize_task(row),
+            project_name: row.get("project_name"),
+            late_by: row.get("late_by"),
+        })
+        .collect())
+}
+
+// adds the user registered under `email` to the project and lets them know
+pub async fn add_participant_db(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    email: &str,
+) -> AppResult<i64> {
+    let mut tx = (&mut *db).begin().await?;
+
+    let user_id: i64 = match db::query("SELECT id FROM \"user\" WHERE email = ?")
+        .bind(email)
+        .fetch_optional(&mut tx)
+        .await?
+    {
+        Some(row) => row.get("id"),
+        None => return Err(AppError::NotFound("No user with that email".to_string())),
+    };
+    let project = db::query("SELECT name, owner, participants FROM project WHERE id = ?")
+        .bind(proj_id)
+        .fetch_one(&mut tx)
         .await?;
+    let mut participants: Vec<String> = project
+        .get::<String, _>("participants")
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(String::from)
+        .collect();
+    if project.get::<i64, _>("owner") == user_id || participants.contains(&user_id.to_string()) {
+        return Err(AppError::Conflict(
+            "They are already a member of the project".to_string(),
+        ));
+    }
+    participants.push(user_id.to_string());
 
-    Ok((result.rows_affected() == 1).then_some(()))
+    db::query("UPDATE project SET participants = ? WHERE id = ?")
+        .bind(participants.join(","))
+        .bind(proj_id)
+        .execute(&mut tx)
+        .await?;
+    let message = format!(
+        "You were added to project \"{}\"",
+        project.get::<String, _>("name")
+    );
+    let link = format!("/project/{}", proj_id);
+    notify(&mut tx, user_id, "membership", &message, &link).await?;
+
+    tx.commit().await?;
+    Ok(user_id)
 }
 
-pub async fn delete_task_db(mut db: Connection<Db>, id: u8) -> Result<Option<()>, sqlx::Error> {
-    let result = sqlx::query!("DELETE FROM proj_tasks WHERE id = ?", id)
+pub async fn remove_participant_db(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    user_id: i64,
+) -> AppResult<()> {
+    let mut tx = (&mut *db).begin().await?;
+
+    let project = db::query("SELECT name, participants FROM project WHERE id = ?")
+        .bind(proj_id)
+        .fetch_one(&mut tx)
+        .await?;
+    let participants: String = project.get("participants");
+    let remaining: Vec<&str> = participants
+        .split(',')
+        .filter(|p| !p.is_empty() && *p != user_id.to_string())
+        .collect();
+    if remaining.len() == participants.split(',').filter(|p| !p.is_empty()).count() {
+        return Err(AppError::NotFound(
+            "They are not a participant of the project".to_string(),
+        ));
+    }
+
+    db::query("UPDATE project SET participants = ? WHERE id = ?")
+        .bind(remaining.join(","))
+        .bind(proj_id)
+        .execute(&mut tx)
+        .await?;
+    let message = format!(
+        "You were removed from project \"{}\"",
+        project.get::<String, _>("name")
+    );
+    notify(&mut tx, user_id, "membership", &message, "").await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn delete_project_db(mut db: Connection<Db>, id: i64) -> AppResult<ProjectEvent> {
+    let mut tx = (&mut *db).begin().await?;
+    let project = db::query("SELECT name, owner FROM project WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Project not found".to_string()))?;
+    let name: String = project.get("name");
+    let owner: i64 = project.get("owner");
+
+    // the project's webhooks hear about it while they still belong to it,
+    // afterwards they only stay around for their delivery history
+    let event = ProjectEvent::new(
+        id,
+        "project.deleted",
+        None,
+        json!({"name": name, "owner": owner}),
+    );
+    queue_event(&mut tx, &event).await?;
+    db::query("UPDATE webhook SET project_deleted = TRUE WHERE project_id = ?")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    db::query("DELETE FROM project WHERE id = ?")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(event)
+}
+
+// deletes the task together with all of its subtasks, with an event for each of them
+pub async fn delete_task_db(mut db: Connection<Db>, id: i64) -> AppResult<Vec<ProjectEvent>> {
+    let mut tx = (&mut *db).begin().await?;
+    let deleted: Vec<ProjectEvent> = db::query(&format!(
+        "{} SELECT id, owner_proj FROM proj_tasks WHERE id IN (SELECT id FROM tree)",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .fetch_all(&mut tx)
+    .await?
+    .iter()
+    .map(|row| {
+        ProjectEvent::new(
+            row.get("owner_proj"),
+            "task.deleted",
+            Some(row.get("id")),
+            json!({}),
+        )
+    })
+    .collect();
+    for event in &deleted {
+        queue_event(&mut tx, event).await?;
+    }
+
+    let result = db::query(&format!(
+        "{} DELETE FROM proj_tasks WHERE id IN (SELECT id FROM tree)",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+    affected(result.rows_affected(), "Task")?;
+
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+// a task with open subtasks or unchecked checklist items is only completed when
+// `confirmed`, in which case everything underneath it is completed as well;
+// returns an event for every task completed, or None when the confirmation is still missing
+pub async fn complete_task_db(
+    mut db: Connection<Db>,
+    user: i64,
+    id: i64,
+    confirmed: bool,
+) -> AppResult<Option<Vec<ProjectEvent>>> {
+    let task_end_date = db::now();
+    let mut tx = (&mut *db).begin().await?;
+
+    let task = db::query("SELECT owner_proj, task_end_date FROM proj_tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
+    if !task.get::<String, _>("task_end_date").is_empty() {
+        return Err(AppError::Conflict("Task is already completed".to_string()));
+    }
+    let proj_id: i64 = task.get("owner_proj");
+
+    let open_subtasks = db::query(&format!(
+        "{} SELECT id, owner_proj, task_start_date FROM proj_tasks
+        WHERE id IN (SELECT id FROM tree) AND id <> ? AND task_end_date = ''",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .bind(id)
+    .fetch_all(&mut tx)
+    .await?;
+    let open_items: i64 = db::query(&format!(
+        "{} SELECT COUNT(*) FROM checklist_item WHERE task_id IN (SELECT id FROM tree) AND NOT done",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await?
+    .get(0);
+
+    if !confirmed && (!open_subtasks.is_empty() || open_items > 0) {
+        return Ok(None);
+    }
+
+    let mut completed: Vec<i64> = open_subtasks.iter().map(|row| row.get("id")).collect();
+    completed.push(id);
+
+    for subtask in open_subtasks {
+        let subtask_id: i64 = subtask.get("id");
+        let start: String = subtask.get("task_start_date");
+        let time_delta = time_delta_between(&start, &task_end_date).unwrap_or_default();
+        append_to_column(&mut tx, subtask_id, "done").await?;
+        db::query("UPDATE proj_tasks SET task_end_date = ?, time_delta = ? WHERE id = ?")
+            .bind(&task_end_date)
+            .bind(time_delta)
+            .bind(subtask_id)
+            .execute(&mut tx)
+            .await?;
+    }
+    db::query(&format!(
+        "{} UPDATE checklist_item SET done = TRUE WHERE task_id IN (SELECT id FROM tree)",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+
+    append_to_column(&mut tx, id, "done").await?;
+    let result =
+        db::query("UPDATE proj_tasks SET task_end_date = ? WHERE id = ? AND task_end_date = ''")
+            .bind(&task_end_date)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::Conflict("Task is already completed".to_string()));
+    }
+    notify_watchers(&mut tx, id, user).await?;
+    let mut events = Vec::with_capacity(completed.len());
+    for task_id in completed {
+        cancel_reminders(&mut tx, task_id).await?;
+        let event = ProjectEvent::new(proj_id, "task.completed", Some(task_id), json!({}));
+        queue_event(&mut tx, &event).await?;
+        events.push(event);
+    }
+
+    tx.commit().await?;
+    Ok(Some(events))
+}
+
+pub async fn get_open_children(
+    db: &mut DbConnection,
+    id: i64,
+) -> AppResult<(Vec<ProjectTask>, Vec<ChecklistItem>)> {
+    let subtasks = db::query(&format!(
+        "{} SELECT * FROM proj_tasks
+        WHERE id IN (SELECT id FROM tree) AND id <> ? AND task_end_date = ''",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+    let items = db::query(&format!(
+        "{} SELECT * FROM checklist_item
+        WHERE task_id IN (SELECT id FROM tree) AND NOT done
+        ORDER BY position, id",
+        TASK_TREE_CTE
+    ))
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok((
+        subtasks.iter().map(serialize_task).collect(),
+        items.iter().map(serialize_checklist_item).collect(),
+    ))
+}
+
+// records that `task_id` is blocked by `blocked_by`, refusing anything that would
+// make a task (transitively) wait on itself
+pub async fn add_dependency_db(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    task_id: i64,
+    blocked_by: i64,
+) -> AppResult<()> {
+    if task_id == blocked_by {
+        return Err(AppError::Validation(
+            "A task cannot block itself".to_string(),
+        ));
+    }
+    let mut tx = (&mut *db).begin().await?;
+
+    let in_project: i64 =
+        db::query("SELECT COUNT(*) FROM proj_tasks WHERE id IN (?, ?) AND owner_proj = ?")
+            .bind(task_id)
+            .bind(blocked_by)
+            .bind(proj_id)
+            .fetch_one(&mut tx)
+            .await?
+            .get(0);
+    if in_project != 2 {
+        return Err(AppError::NotFound(
+            "Both tasks must belong to the project".to_string(),
+        ));
+    }
+
+    // a cycle appears if `blocked_by` already waits on `task_id`, directly or not
+    let cycle: bool = db::query(
+        "
+        WITH RECURSIVE blockers(id) AS (
+            SELECT blocked_by FROM task_dependency WHERE task_id = ?
+            UNION
+            SELECT d.blocked_by FROM task_dependency d JOIN blockers ON d.task_id = blockers.id
+        )
+        SELECT EXISTS (SELECT 1 FROM blockers WHERE id = ?)",
+    )
+    .bind(blocked_by)
+    .bind(task_id)
+    .fetch_one(&mut tx)
+    .await?
+    .get(0);
+    if cycle {
+        return Err(AppError::Conflict(
+            "That dependency would create a cycle".to_string(),
+        ));
+    }
+
+    db::query(
+        "INSERT INTO task_dependency (task_id, blocked_by) VALUES (?, ?)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(task_id)
+    .bind(blocked_by)
+    .execute(&mut tx)
+    .await?;
+
+    Ok(tx.commit().await?)
+}
+
+pub async fn delete_dependency_db(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    task_id: i64,
+    blocked_by: i64,
+) -> AppResult<()> {
+    let result = db::query(
+        "DELETE FROM task_dependency
+        WHERE task_id = ? AND blocked_by = ?
+            AND task_id IN (SELECT id FROM proj_tasks WHERE owner_proj = ?)",
+    )
+    .bind(task_id)
+    .bind(blocked_by)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    affected(result.rows_affected(), "Dependency")
+}
+
+pub async fn set_task_estimate(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    id: i64,
+    estimate: Option<i64>,
+) -> AppResult<()> {
+    let result = db::query("UPDATE proj_tasks SET estimate = ? WHERE id = ? AND owner_proj = ?")
+        .bind(estimate)
+        .bind(id)
+        .bind(proj_id)
         .execute(&mut *db)
         .await?;
 
-    Ok((result.rows_affected() == 1).then_some(()))
+    affected(result.rows_affected(), "Task")
+}
+
+// the longest chain of dependent tasks, weighing every task by its estimate;
+// expects `tasks` to come from get_all_tasks_for_project so `blocked_by` is filled in
+pub fn critical_path(tasks: &[ProjectTask]) -> CriticalPath {
+    // earliest finish of every task and the blocker it waited on last
+    let mut finish: HashMap<i64, (i64, Option<i64>)> = HashMap::new();
+    let mut remaining: Vec<&ProjectTask> = tasks.iter().filter(|task| task.id.is_some()).collect();
+
+    while !remaining.is_empty() {
+        let before = remaining.len();
+        remaining.retain(|task| {
+            // blockers outside this project don't take part in its schedule
+            let blockers: Vec<i64> = task
+                .blocked_by
+                .iter()
+                .filter(|id| tasks.iter().any(|t| t.id == Some(**id)))
+                .copied()
+                .collect();
+            if !blockers.iter().all(|id| finish.contains_key(id)) {
+                return true;
+            }
+            let (start, waited_on) = blockers
+                .iter()
+                .map(|id| (finish[id].0, Some(*id)))
+                .max()
+                .unwrap_or((0, None));
+            finish.insert(
+                task.id.unwrap(),
+                (start + task.estimate.unwrap_or(0), waited_on),
+            );
+            false
+        });
+        // add_dependency_db refuses cycles, but never loop forever on bad data
+        if remaining.len() == before {
+            break;
+        }
+    }
+
+    let last = finish
+        .iter()
+        .map(|(id, (end, _))| (*end, *id))
+        .max()
+        .map(|(_, id)| id);
+    let mut path = vec![];
+    let mut current = last;
+    while let Some(id) = current {
+        path.push(id);
+        current = finish[&id].1;
+    }
+    path.reverse();
+
+    CriticalPath {
+        duration: last.map(|id| finish[&id].0).unwrap_or(0),
+        tasks: path
+            .iter()
+            .filter_map(|id| tasks.iter().find(|task| task.id == Some(*id)))
+            .cloned()
+            .collect(),
+    }
+}
+
+pub async fn add_checklist_item(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    task_id: i64,
+    text: &str,
+) -> AppResult<()> {
+    let result = db::query(
+        "INSERT INTO checklist_item (task_id, text, position)
+        SELECT id, ?, (SELECT COUNT(*) FROM checklist_item WHERE task_id = proj_tasks.id)
+        FROM proj_tasks WHERE id = ? AND owner_proj = ?",
+    )
+    .bind(text)
+    .bind(task_id)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    affected(result.rows_affected(), "Task")
+}
+
+pub async fn toggle_checklist_item(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    task_id: i64,
+    id: i64,
+) -> AppResult<()> {
+    let result = db::query(
+        "UPDATE checklist_item SET done = NOT done
+        WHERE id = ? AND task_id IN (SELECT id FROM proj_tasks WHERE id = ? AND owner_proj = ?)",
+    )
+    .bind(id)
+    .bind(task_id)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    affected(result.rows_affected(), "Checklist item")
+}
+
+pub async fn delete_checklist_item(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    task_id: i64,
+    id: i64,
+) -> AppResult<()> {
+    let result = db::query(
+        "DELETE FROM checklist_item
+        WHERE id = ? AND task_id IN (SELECT id FROM proj_tasks WHERE id = ? AND owner_proj = ?)",
+    )
+    .bind(id)
+    .bind(task_id)
+    .bind(proj_id)
+    .execute(&mut *db)
+    .await?;
+
+    affected(result.rows_affected(), "Checklist item")
+}
+
+pub fn is_wip_column(status: &str) -> bool {
+    matches!(status, "in_progress" | "review")
+}
+
+pub async fn get_board_for_project(
+    db: &mut DbConnection,
+    proj_id: i64,
+) -> AppResult<Vec<BoardColumn>> {
+    let limits = db::query("SELECT status, wip_limit FROM board_column WHERE owner_proj = ?")
+        .bind(proj_id)
+        .fetch_all(&mut *db)
+        .await?;
+    let tasks = db::query("SELECT * FROM proj_tasks WHERE owner_proj = ? ORDER BY position, id")
+        .bind(proj_id)
+        .fetch_all(&mut *db)
+        .await?;
+
+    let columns = TASK_STATUSES
+        .iter()
+        .map(|(status, label)| BoardColumn {
+            status: status.to_string(),
+            label: label.to_string(),
+            wip_limit: limits
+                .iter()
+                .find(|row| row.get::<String, _>("status") == *status)
+                .and_then(|row| row.get::<Option<i64>, _>("wip_limit")),
+            tasks: tasks
+                .iter()
+                .filter(|row| row.get::<String, _>("status") == *status)
+                .map(serialize_task)
+                .collect(),
+        })
+        .collect();
+
+    Ok(columns)
+}
+
+// refuses another card in a column already holding `in_column` of them when that's its limit
+async fn check_wip_limit(
+    db: &mut DbConnection,
+    proj_id: i64,
+    status: &str,
+    in_column: i64,
+) -> AppResult<()> {
+    let wip_limit: Option<i64> =
+        db::query("SELECT wip_limit FROM board_column WHERE owner_proj = ? AND status = ?")
+            .bind(proj_id)
+            .bind(status)
+            .fetch_optional(db)
+            .await?
+            .and_then(|row| row.get("wip_limit"));
+    match wip_limit {
+        Some(wip_limit) if in_column >= wip_limit => Err(AppError::Conflict(format!(
+            "WIP limit of {} reached",
+            wip_limit
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// moves a task to `position` within the `status` column, shifting the other cards
+// of both columns so positions stay contiguous; all of it happens in one transaction
+// returns the task.completed event when the move completed the task
+pub async fn move_task_db(
+    mut db: Connection<Db>,
+    user: i64,
+    proj_id: i64,
+    task_id: i64,
+    status: &str,
+    position: i64,
+) -> AppResult<Option<ProjectEvent>> {
+    if !TASK_STATUSES.iter().any(|(s, _)| *s == status) {
+        return Err(AppError::Validation(format!(
+            "Unknown task status: {}",
+            status
+        )));
+    }
+    let mut tx = (&mut *db).begin().await?;
+
+    let row = db::query(
+        "SELECT status, position, task_start_date, task_end_date FROM proj_tasks WHERE id = ? AND owner_proj = ?",
+    )
+    .bind(task_id)
+    .bind(proj_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
+    let old_status: String = row.get("status");
+    let old_position: i64 = row.get("position");
+
+    let in_column: i64 = db::query(
+        "SELECT COUNT(*) FROM proj_tasks WHERE owner_proj = ? AND status = ? AND id <> ?",
+    )
+    .bind(proj_id)
+    .bind(status)
+    .bind(task_id)
+    .fetch_one(&mut tx)
+    .await?
+    .get(0);
+
+    if old_status != status {
+        check_wip_limit(&mut tx, proj_id, status, in_column).await?;
+    }
+
+    let position = position.clamp(0, in_column);
+
+    db::query(
+        "UPDATE proj_tasks SET position = position - 1
+        WHERE owner_proj = ? AND status = ? AND position > ? AND id <> ?",
+    )
+    .bind(proj_id)
+    .bind(&old_status)
+    .bind(old_position)
+    .bind(task_id)
+    .execute(&mut tx)
+    .await?;
+
+    db::query(
+        "UPDATE proj_tasks SET position = position + 1
+        WHERE owner_proj = ? AND status = ? AND position >= ? AND id <> ?",
+    )
+    .bind(proj_id)
+    .bind(status)
+    .bind(position)
+    .bind(task_id)
+    .execute(&mut tx)
+    .await?;
+
+    db::query("UPDATE proj_tasks SET status = ?, position = ? WHERE id = ?")
+        .bind(status)
+        .bind(position)
+        .bind(task_id)
+        .execute(&mut tx)
+        .await?;
+
+    // dropping a card into "done" completes the task the same way complete_task does
+    let task_end_date: String = row.get("task_end_date");
+    let mut completed = None;
+    if status == "done" && task_end_date.is_empty() {
+        let start: String = row.get("task_start_date");
+        let end = db::now();
+        let time_delta = time_delta_between(start.as_str(), end.as_str()).unwrap_or_default();
+        db::query("UPDATE proj_tasks SET task_end_date = ?, time_delta = ? WHERE id = ?")
+            .bind(end)
+            .bind(time_delta)
+            .bind(task_id)
+            .execute(&mut tx)
+            .await?;
+        notify_watchers(&mut tx, task_id, user).await?;
+        cancel_reminders(&mut tx, task_id).await?;
+        let event = ProjectEvent::new(proj_id, "task.completed", Some(task_id), json!({}));
+        queue_event(&mut tx, &event).await?;
+        completed = Some(event);
+    }
+    // and dragging it back out reopens it the same way reopen_task does
+    if status != "done" && !task_end_date.is_empty() {
+        log_task_times(&mut tx, proj_id, task_id, "reopened").await?;
+        db::query("UPDATE proj_tasks SET task_end_date = '', time_delta = 0 WHERE id = ?")
+            .bind(task_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(completed)
 }
 
-pub async fn complete_task_db(mut db: Connection<Db>, id: u8) -> Result<Option<()>, sqlx::Error> {
-    let task_end_date = Utc::now().to_string();
-    let result = sqlx::query!(
-        "UPDATE proj_tasks SET task_end_date = ? WHERE id = ?",
-        task_end_date,
-        id
+pub async fn set_wip_limit(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    status: &str,
+    wip_limit: Option<i64>,
+) -> AppResult<()> {
+    if !is_wip_column(status) {
+        return Err(AppError::Validation(format!("{} has no WIP limit", status)));
+    }
+    let result = db::query(
+        "INSERT INTO board_column (owner_proj, status, wip_limit) VALUES (?, ?, ?)
+        ON CONFLICT (owner_proj, status) DO UPDATE SET wip_limit = excluded.wip_limit",
+    )
+    .bind(proj_id)
+    .bind(status)
+    .bind(wip_limit)
+    .execute(&mut *db)
+    .await?;
+
+    affected(result.rows_affected(), "Board column")
+}
+
+// copies a task's current dates into task_time_log before they get replaced
+async fn log_task_times(
+    db: &mut DbConnection,
+    proj_id: i64,
+    id: i64,
+    reason: &str,
+) -> Result<u64, sqlx::Error> {
+    let recorded = db::now();
+    let result = db::query(
+        "INSERT INTO task_time_log (task_id, task_start_date, task_end_date, time_delta, reason, recorded)
+        SELECT id, task_start_date, task_end_date, time_delta, ?, ?
+        FROM proj_tasks WHERE id = ? AND owner_proj = ?",
+    )
+    .bind(reason)
+    .bind(recorded)
+    .bind(id)
+    .bind(proj_id)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// takes a task out of its board column, closing the gap it leaves behind, and puts it
+// at the end of the `status` column
+async fn append_to_column(db: &mut DbConnection, id: i64, status: &str) -> Result<(), sqlx::Error> {
+    let row = db::query("SELECT owner_proj, status, position FROM proj_tasks WHERE id = ?")
+        .bind(id)
+        .fetch_one(&mut *db)
+        .await?;
+    if row.get::<String, _>("status") == status {
+        return Ok(());
+    }
+
+    db::query(
+        "UPDATE proj_tasks SET position = position - 1
+        WHERE owner_proj = ? AND status = ? AND position > ?",
     )
+    .bind(row.get::<i64, _>("owner_proj"))
+    .bind(row.get::<String, _>("status"))
+    .bind(row.get::<i64, _>("position"))
     .execute(&mut *db)
     .await?;
+    db::query(
+        "UPDATE proj_tasks
+        SET status = ?,
+            position = (SELECT COUNT(*) FROM proj_tasks t
+                WHERE t.owner_proj = proj_tasks.owner_proj AND t.status = ?)
+        WHERE id = ?",
+    )
+    .bind(status)
+    .bind(status)
+    .bind(id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn reopen_task_db(mut db: Connection<Db>, proj_id: i64, id: i64) -> AppResult<()> {
+    let mut tx = (&mut *db).begin().await?;
+
+    let completed: Option<String> = db::query(
+        "SELECT task_end_date FROM proj_tasks
+        WHERE id = ? AND owner_proj = ? AND task_end_date <> ''",
+    )
+    .bind(id)
+    .bind(proj_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .map(|row| row.get("task_end_date"));
+    if completed.is_none() {
+        return Err(AppError::NotFound("No such completed task".to_string()));
+    }
+
+    log_task_times(&mut tx, proj_id, id, "reopened").await?;
+    append_to_column(&mut tx, id, "todo").await?;
+    let result = db::query(
+        "UPDATE proj_tasks SET task_end_date = '', time_delta = 0
+        WHERE id = ? AND owner_proj = ?",
+    )
+    .bind(id)
+    .bind(proj_id)
+    .execute(&mut tx)
+    .await?;
+    affected(result.rows_affected(), "Task")?;
+
+    Ok(tx.commit().await?)
+}
+
+pub async fn get_task_times(db: &mut DbConnection, id: i64) -> AppResult<TaskTimes> {
+    let row = db::query("SELECT * FROM proj_tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
+    let task = serialize_task(&row);
+    let history = db::query("SELECT * FROM task_time_log WHERE task_id = ? ORDER BY id DESC")
+        .bind(id)
+        .fetch_all(&mut *db)
+        .await?
+        .into_iter()
+        .map(|row| TaskTimeLog {
+            id: row.get("id"),
+            task_start_date: row.get("task_start_date"),
+            task_end_date: row.get("task_end_date"),
+            time_delta: row.get("time_delta"),
+            reason: row.get("reason"),
+            recorded: row.get("recorded"),
+        })
+        .collect();
+
+    Ok(TaskTimes {
+        start_input: format_for_datepicker(&task.task_start_date),
+        end_input: format_for_datepicker(&task.task_end_date),
+        task,
+        history,
+    })
+}
+
+// replaces the recorded start and end of a task, an empty end date leaves it open;
+// the task moves to or out of the done column to match
+pub async fn edit_task_times_db(
+    mut db: Connection<Db>,
+    proj_id: i64,
+    id: i64,
+    task_start_date: &str,
+    task_end_date: &str,
+) -> AppResult<()> {
+    let time_delta = time_delta_between(task_start_date, task_end_date).unwrap_or_default();
+
+    let mut tx = (&mut *db).begin().await?;
+    affected(
+        log_task_times(&mut tx, proj_id, id, "corrected").await?,
+        "Task",
+    )?;
+    let result = db::query(
+        "UPDATE proj_tasks SET task_start_date = ?, task_end_date = ?, time_delta = ?
+        WHERE id = ? AND owner_proj = ?",
+    )
+    .bind(task_start_date)
+    .bind(task_end_date)
+    .bind(time_delta)
+    .bind(id)
+    .bind(proj_id)
+    .execute(&mut tx)
+    .await?;
+    affected(result.rows_affected(), "Task")?;
+
+    let status: String = db::query("SELECT status FROM proj_tasks WHERE id = ?")
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await?
+        .get("status");
+    if task_end_date.is_empty() && status == "done" {
+        append_to_column(&mut tx, id, "todo").await?;
+    } else if !task_end_date.is_empty() && status != "done" {
+        append_to_column(&mut tx, id, "done").await?;
+    }
 
-    Ok((result.rows_affected() == 1).then_some(()))
+    Ok(tx.commit().await?)
 }
 
-// pub async fn add_time_delta(mut db: Connection<Db>, id: u8) -> Result<Option<()>, sqlx::Error> {
+// pub async fn add_time_delta(mut db: Connection<Db>, id: i64) -> Result<Option<()>, sqlx::Error> {
 //     let result = sqlx::query!(
 //         "
 //         UPDATE proj_tasks
@@ -436,61 +1876,60 @@ pub async fn complete_task_db(mut db: Connection<Db>, id: u8) -> Result<Option<(
 //     }
 // }
 
-pub async fn add_time_delta(mut db: Connection<Db>, id: u8) -> Result<Option<()>, sqlx::Error> {
-    let result = sqlx::query(
+pub async fn add_time_delta(mut db: Connection<Db>, id: i64) -> AppResult<()> {
+    let row = db::query(
         "
         SELECT task_start_date, task_end_date FROM proj_tasks WHERE id = ?
         ",
     )
     .bind(id)
-    .fetch_one(&mut *db)
-    .await;
+    .fetch_optional(&mut *db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Task not found".to_string()))?;
 
-    if let Ok(row) = result {
-        let start: String = row.get("task_start_date");
-        let end: String = row.get("task_end_date");
-
-        let start_n = NaiveDateTime::parse_from_str(start.as_str(), "%Y-%m-%d %H:%M:%S%.f %Z")
-            .expect("Failed to parse start date");
-        let end_n = NaiveDateTime::parse_from_str(end.as_str(), "%Y-%m-%d %H:%M:%S%.f %Z")
-            .expect("Failed to parse end date");
-
-        // let start_utc: DateTime<Utc> = DateTime::<Utc>::from_utc(start_n, Utc);
-        // println!("start_utc: {:#?}", start_utc);
-        // let end_utc: DateTime<Utc> = DateTime::<Utc>::from_utc(end_n, Utc);
-        // println!("end_utc: {:#?}\n", end_utc);
-
-        let time_delta = end_n - start_n;
-        // let time_delta = format_duration(time_delta);
-
-        let time_delta = time_delta.num_seconds();
-
-        sqlx::query!(
-            "
-            UPDATE proj_tasks
-            SET time_delta = ?
-            WHERE id = ?
-            ",
-            time_delta,
-            id
-        )
-        .execute(&mut *db)
-        .await?;
+    let start: String = row.get("task_start_date");
+    let end: String = row.get("task_end_date");
+    let time_delta = time_delta_between(start.as_str(), end.as_str()).ok_or_else(|| {
+        AppError::Validation("The task's start or end date can't be read".to_string())
+    })?;
 
-        Ok(Some(()))
-    } else {
-        error!("Failed to add time delta");
-        Ok(None)
-    }
+    db::query(
+        "
+        UPDATE proj_tasks
+        SET time_delta = ?
+        WHERE id = ?
+        ",
+    )
+    .bind(time_delta)
+    .bind(id)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
 }
 
 // parses from "2020-01-01T00:00:00" to "2020-01-01 00:00:00"
-// "2020-01-01T00:00:00" is the format that the datepicker returns
-// "2020-01-01 00:00:00" is the format generated by 'DATETIME DEFAULT CURRENT_TIMESTAMP' in sqlite
-fn _parse_date(date: &str) -> String {
-    let parsed_end_date = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
-        .expect("Failed to parse date string");
-    parsed_end_date.format("%Y-%m-%d %H:%M:%S").to_string()
+// "2020-01-01T00:00:00" is the format that the datepicker returns (seconds are dropped without step="1")
+// "2020-01-01 00:00:00" is db::TIMESTAMP_FORMAT, the one every date is stored in
+pub fn parse_date(date: &str) -> Option<String> {
+    let parsed_date = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M"))
+        .ok()?;
+    Some(parsed_date.format(db::TIMESTAMP_FORMAT).to_string())
+}
+
+// seconds between two stored timestamps
+fn time_delta_between(start: &str, end: &str) -> Option<i64> {
+    let start_n = NaiveDateTime::parse_from_str(start, db::TIMESTAMP_FORMAT).ok()?;
+    let end_n = NaiveDateTime::parse_from_str(end, db::TIMESTAMP_FORMAT).ok()?;
+    Some((end_n - start_n).num_seconds())
+}
+
+// the reverse of `parse_date`, for prefilling a datepicker
+fn format_for_datepicker(date: &str) -> String {
+    NaiveDateTime::parse_from_str(date, db::TIMESTAMP_FORMAT)
+        .map(|date| date.format("%Y-%m-%dT%H:%M:%S").to_string())
+        .unwrap_or_default()
 }
 
 fn _format_duration(duration: Duration) -> String {
@@ -555,7 +1994,7 @@ fn _format_duration(duration: Duration) -> String {
 
 async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
     match Db::fetch(&rocket) {
-        Some(db) => match sqlx::migrate!("db/migrations").run(&**db).await {
+        Some(db) => match MIGRATOR.run(&**db).await {
             Ok(_) => Ok(rocket),
             Err(e) => {
                 error!("Failed to initialize SQLx database: {}", e);
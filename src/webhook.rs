@@ -0,0 +1,589 @@
+// Outgoing webhooks. Every project event is turned into one delivery per matching
+// subscription, in the same transaction as the change it reports; the
+// webhook.deliver job posts them with exponential backoff.
+//
+// Each request carries the JSON payload as its body and these headers:
+//   X-RRS-Event:     the event name, e.g. "task.completed"
+//   X-RRS-Delivery:  the id of the delivery, stable across retries
+//   X-RRS-Signature: "sha256=" + hex(HMAC-SHA256(secret, body))
+//
+// Urls pointing at loopback, link-local or private addresses are refused unless
+// `webhooks.allow_private_hosts` is set, e.g. for a receiver on the same network.
+
+// rocket's FromForm derive still emits the removed private_in_public lint
+#![allow(renamed_and_removed_lints)]
+
+use crate::db::{self, DbConnection, DbPool, DbRow};
+use crate::get_flash_msg;
+use crate::live::ProjectEvent;
+use crate::user::{Db, Projects, User};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use rocket::form::{Contextual, Form};
+use rocket::request::FlashMessage;
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::net::lookup_host;
+use rocket::State;
+use rocket_db_pools::{sqlx, sqlx::Row, Connection};
+use rocket_dyn_templates::{context, Template};
+use sha2::Sha256;
+use std::net::IpAddr;
+
+pub const WEBHOOK_EVENTS: [&str; 7] = [
+    "project.created",
+    "project.updated",
+    "project.deleted",
+    "task.created",
+    "task.completed",
+    "task.deleted",
+    // only sent to the webhooks of the user being reminded
+    "task.reminder",
+];
+
+// a delivery is given up on after this many failed attempts
+const MAX_ATTEMPTS: i64 = 8;
+// the first retry waits this long, every further one twice as long as the last
+const RETRY_BASE_SECONDS: i64 = 30;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Webhook {
+    pub id: i64,
+    pub owner: i64,
+    // None subscribes to every project of the owner
+    pub project_id: Option<i64>,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    // 'pending', 'delivered' or 'failed'
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt: String,
+    pub response_code: Option<i64>,
+    pub last_error: String,
+    pub created: String,
+}
+
+fn serialize_webhook(row: &DbRow) -> Webhook {
+    Webhook {
+        id: row.get("id"),
+        owner: row.get("owner"),
+        project_id: row.get("project_id"),
+        url: row.get("url"),
+        secret: row.get("secret"),
+        events: row
+            .get::<String, _>("events")
+            .split(',')
+            .filter(|e| !e.is_empty())
+            .map(String::from)
+            .collect(),
+        active: row.get("active"),
+        created: row.get("created"),
+    }
+}
+
+fn serialize_delivery(row: &DbRow) -> WebhookDelivery {
+    WebhookDelivery {
+        id: row.get("id"),
+        webhook_id: row.get("webhook_id"),
+        event: row.get("event"),
+        payload: row.get("payload"),
+        status: row.get("status"),
+        attempts: row.get("attempts"),
+        next_attempt: row.get("next_attempt"),
+        response_code: row.get("response_code"),
+        last_error: row.get("last_error"),
+        created: row.get("created"),
+    }
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// how long to wait after the `attempts`th failure
+fn backoff(attempts: i64) -> Duration {
+    Duration::seconds(RETRY_BASE_SECONDS << (attempts - 1).clamp(0, 16))
+}
+
+pub async fn get_webhooks_for_project(
+    mut db: Connection<Db>,
+    owner: i64,
+    proj_id: i64,
+) -> Result<(Vec<Webhook>, Vec<WebhookDelivery>), String> {
+    let fail = |e: sqlx::Error| format!("Failed to get webhooks: {}", e);
+
+    let webhooks: Vec<Webhook> = db::query(
+        "SELECT * FROM webhook
+        WHERE owner = ? AND (project_id = ? OR project_id IS NULL) AND NOT project_deleted
+        ORDER BY id",
+    )
+    .bind(owner)
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await
+    .map_err(fail)?
+    .iter()
+    .map(serialize_webhook)
+    .collect();
+    let deliveries = db::query(
+        "SELECT d.* FROM webhook_delivery d
+        JOIN webhook w ON w.id = d.webhook_id
+        WHERE w.owner = ? AND (w.project_id = ? OR w.project_id IS NULL) AND NOT w.project_deleted
+        ORDER BY d.id DESC
+        LIMIT 50",
+    )
+    .bind(owner)
+    .bind(proj_id)
+    .fetch_all(&mut *db)
+    .await
+    .map_err(fail)?
+    .iter()
+    .map(serialize_delivery)
+    .collect();
+
+    Ok((webhooks, deliveries))
+}
+
+pub async fn add_webhook(
+    mut db: Connection<Db>,
+    owner: i64,
+    proj_id: Option<i64>,
+    url: &str,
+    secret: &str,
+    events: &[&str],
+) -> Result<i64, sqlx::Error> {
+    let row = db::query(
+        "INSERT INTO webhook (owner, project_id, url, secret, events, created)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id",
+    )
+    .bind(owner)
+    .bind(proj_id)
+    .bind(url)
+    .bind(secret)
+    .bind(events.join(","))
+    .bind(db::now())
+    .fetch_one(&mut *db)
+    .await?;
+
+    Ok(row.get("id"))
+}
+
+pub async fn delete_webhook_db(
+    mut db: Connection<Db>,
+    owner: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let result = db::query("DELETE FROM webhook WHERE id = ? AND owner = ?")
+        .bind(id)
+        .bind(owner)
+        .execute(&mut *db)
+        .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+async fn queue_delivery(
+    db: &mut DbConnection,
+    webhook_id: i64,
+    event: &str,
+    payload: &str,
+) -> Result<(), sqlx::Error> {
+    let now = db::now();
+    db::query(
+        "INSERT INTO webhook_delivery (webhook_id, event, payload, next_attempt, created)
+        VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(webhook_id)
+    .bind(event)
+    .bind(payload)
+    .bind(&now)
+    .bind(&now)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// queues a delivery of `event` for every active subscription it matches, meant to run
+// in the transaction making the change so neither goes missing without the other
+pub async fn queue_event(db: &mut DbConnection, event: &ProjectEvent) -> Result<(), sqlx::Error> {
+    let owner: Option<i64> = db::query("SELECT owner FROM project WHERE id = ?")
+        .bind(event.project_id)
+        .fetch_optional(&mut *db)
+        .await?
+        .map(|row| row.get("owner"));
+    match owner {
+        Some(owner) => queue_user_event(db, owner, event).await,
+        None => Ok(()),
+    }
+}
+
+// queues a delivery of `event` for every matching subscription of `owner`
+pub async fn queue_user_event(
+    db: &mut DbConnection,
+    owner: i64,
+    event: &ProjectEvent,
+) -> Result<(), sqlx::Error> {
+    let webhooks: Vec<Webhook> = db::query(
+        "SELECT * FROM webhook
+        WHERE active AND owner = ? AND (project_id = ? OR project_id IS NULL) AND NOT project_deleted
+        AND (',' || events || ',') LIKE ('%,' || ? || ',%')",
+    )
+    .bind(owner)
+    .bind(event.project_id)
+    .bind(&event.event)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(serialize_webhook)
+    .collect();
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let payload = json!({
+        "event": event.event,
+        "project_id": event.project_id,
+        "task_id": event.task_id,
+        "data": event.data,
+        "timestamp": Utc::now().to_rfc3339(),
+    })
+    .to_string();
+    for webhook in webhooks {
+        queue_delivery(db, webhook.id, &event.event, &payload).await?;
+    }
+    Ok(())
+}
+
+// posts every delivery that is due and records how it went, run by the job runner
+pub async fn send_due_deliveries(
+    pool: &DbPool,
+    client: &reqwest::Client,
+) -> Result<(), sqlx::Error> {
+    let due = db::query(
+        "SELECT d.*, w.url, w.secret FROM webhook_delivery d
+        JOIN webhook w ON w.id = d.webhook_id
+        WHERE d.status = 'pending' AND d.next_attempt <= ?
+        ORDER BY d.id
+        LIMIT 20",
+    )
+    .bind(db::now())
+    .fetch_all(pool)
+    .await?;
+
+    for row in due {
+        let delivery = serialize_delivery(&row);
+        let url: String = row.get("url");
+        let secret: String = row.get("secret");
+
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-RRS-Event", &delivery.event)
+            .header("X-RRS-Delivery", delivery.id.to_string())
+            .header(
+                "X-RRS-Signature",
+                sign(&secret, delivery.payload.as_bytes()),
+            )
+            .body(delivery.payload.clone())
+            .send()
+            .await;
+        let (response_code, last_error) = match response {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16() as i64), String::new())
+            }
+            Ok(response) => (
+                Some(response.status().as_u16() as i64),
+                format!("unexpected status {}", response.status()),
+            ),
+            Err(e) => (None, e.to_string()),
+        };
+
+        let attempts = delivery.attempts + 1;
+        let status = if last_error.is_empty() {
+            "delivered"
+        } else if attempts >= MAX_ATTEMPTS {
+            "failed"
+        } else {
+            "pending"
+        };
+        let next_attempt = db::timestamp(Utc::now() + backoff(attempts));
+        db::query(
+            "UPDATE webhook_delivery
+            SET status = ?, attempts = ?, next_attempt = ?, response_code = ?, last_error = ?
+            WHERE id = ?",
+        )
+        .bind(status)
+        .bind(attempts)
+        .bind(next_attempt)
+        .bind(response_code)
+        .bind(last_error)
+        .bind(delivery.id)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+// queues a fresh copy of an earlier delivery, keeping the original in the log
+pub async fn redeliver_db(
+    mut db: Connection<Db>,
+    owner: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let now = db::now();
+    let result = db::query(
+        "INSERT INTO webhook_delivery (webhook_id, event, payload, next_attempt, created)
+        SELECT d.webhook_id, d.event, d.payload, ?, ? FROM webhook_delivery d
+        JOIN webhook w ON w.id = d.webhook_id
+        WHERE d.id = ? AND w.owner = ?",
+    )
+    .bind(&now)
+    .bind(&now)
+    .bind(id)
+    .bind(owner)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+pub async fn ping_webhook(
+    mut db: Connection<Db>,
+    owner: i64,
+    id: i64,
+) -> Result<Option<()>, sqlx::Error> {
+    let now = db::now();
+    let payload = json!({"event": "ping", "webhook_id": id, "timestamp": Utc::now().to_rfc3339()});
+    let result = db::query(
+        "INSERT INTO webhook_delivery (webhook_id, event, payload, next_attempt, created)
+        SELECT id, 'ping', ?, ?, ? FROM webhook WHERE id = ? AND owner = ?",
+    )
+    .bind(payload.to_string())
+    .bind(&now)
+    .bind(&now)
+    .bind(id)
+    .bind(owner)
+    .execute(&mut *db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(()))
+}
+
+#[get("/project/<id>/webhooks")]
+async fn webhooks_get(
+    db: Connection<Db>,
+    user: &User,
+    projects: Projects,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, Flash<Redirect>> {
+    let project = match projects.0.into_iter().find(|p| p.id == Some(id)) {
+        Some(project) => project,
+        None => {
+            return Err(Flash::error(
+                Redirect::to(uri!("/profile")),
+                "Hmm... That didn't work 🙃",
+            ))
+        }
+    };
+    let msg = get_flash_msg(flash).unwrap_or_default();
+    let (webhooks, deliveries) = get_webhooks_for_project(db, user.id.unwrap(), id)
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            (vec![], vec![])
+        });
+    let events = WEBHOOK_EVENTS;
+    let context = context! {user, project, webhooks, deliveries, events, msg};
+    Ok(Template::render("webhooks", context))
+}
+
+#[get("/project/<_id>/webhooks", rank = 2)]
+async fn webhooks_get_no_auth(_id: i64) -> Redirect {
+    Redirect::to(uri!("/login"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WebhookConfig {
+    // lets webhooks post to loopback, link-local and private addresses
+    #[serde(default)]
+    pub allow_private_hosts: bool,
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(IpAddr::V4(ip)),
+            // fc00::/7 is unique local, fe80::/10 link-local
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+// resolves the url's host and refuses it when any of its addresses isn't public
+async fn check_host(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("Invalid webhook url: {}", e))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| "A webhook url needs a host".to_string())?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut addrs = lookup_host((host, port))
+        .await
+        .map_err(|_| format!("Could not resolve {}", host))?;
+    if addrs.any(|addr| is_private(addr.ip())) {
+        return Err("Webhooks can't post to local or private addresses".to_string());
+    }
+    Ok(())
+}
+
+fn validate_url<'v>(url: &&str) -> rocket::form::Result<'v, ()> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        Err(rocket::form::Error::validation(
+            "webhook urls need to be http(s)",
+        ))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+struct WebhookForm<'v> {
+    #[field(validate = validate_url())]
+    url: &'v str,
+    #[field(validate = len(8..))]
+    secret: &'v str,
+    #[field(validate = len(1..))]
+    events: Vec<&'v str>,
+    all_projects: bool,
+}
+
+#[post("/project/<id>/webhooks", data = "<form>")]
+#[allow(clippy::redundant_locals)]
+async fn add_webhook_post<'r>(
+    db: Connection<Db>,
+    form: Form<Contextual<'r, WebhookForm<'r>>>,
+    user: &User,
+    projects: Projects,
+    config: &State<WebhookConfig>,
+    id: i64,
+) -> Flash<Redirect> {
+    let webhooks = Redirect::to(uri!(webhooks_get(id)));
+    if !projects.0.iter().any(|p| p.id == Some(id)) {
+        return Flash::error(webhooks, "Hmm... That didn't work 🙃");
+    }
+    let form_data =
+        match form.value {
+            Some(ref form_data) => form_data,
+            None => return Flash::error(
+                webhooks,
+                "A webhook needs an http(s) url, a secret of 8+ characters and at least one event",
+            ),
+        };
+    if !form_data
+        .events
+        .iter()
+        .all(|event| WEBHOOK_EVENTS.contains(event))
+    {
+        return Flash::error(webhooks, "Unknown webhook event");
+    }
+    if !config.allow_private_hosts {
+        if let Err(e) = check_host(form_data.url).await {
+            return Flash::error(webhooks, e);
+        }
+    }
+    let proj_id = (!form_data.all_projects).then_some(id);
+    let result = add_webhook(
+        db,
+        user.id.unwrap(),
+        proj_id,
+        form_data.url,
+        form_data.secret,
+        &form_data.events,
+    )
+    .await;
+    match result {
+        Ok(_) => Flash::success(webhooks, "Webhook added"),
+        Err(_) => Flash::error(webhooks, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/delete/project/<proj_id>/webhook/<id>")]
+async fn delete_webhook(db: Connection<Db>, user: &User, proj_id: i64, id: i64) -> Flash<Redirect> {
+    let webhooks = Redirect::to(uri!(webhooks_get(proj_id)));
+    match delete_webhook_db(db, user.id.unwrap(), id).await {
+        Ok(Some(_)) => Flash::success(webhooks, "Webhook deleted"),
+        _ => Flash::error(webhooks, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/project/<proj_id>/webhook/<id>/ping")]
+async fn ping(db: Connection<Db>, user: &User, proj_id: i64, id: i64) -> Flash<Redirect> {
+    let webhooks = Redirect::to(uri!(webhooks_get(proj_id)));
+    match ping_webhook(db, user.id.unwrap(), id).await {
+        Ok(Some(_)) => Flash::success(webhooks, "Ping queued"),
+        _ => Flash::error(webhooks, "Hmm... That didn't work 🙃"),
+    }
+}
+
+#[get("/project/<proj_id>/webhook-delivery/<id>/redeliver")]
+async fn redeliver(db: Connection<Db>, user: &User, proj_id: i64, id: i64) -> Flash<Redirect> {
+    let webhooks = Redirect::to(uri!(webhooks_get(proj_id)));
+    match redeliver_db(db, user.id.unwrap(), id).await {
+        Ok(Some(_)) => Flash::success(webhooks, "Delivery queued again"),
+        _ => Flash::error(webhooks, "Hmm... That didn't work 🙃"),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("webhook stage", |rocket| async {
+        let config: WebhookConfig = rocket
+            .figment()
+            .extract_inner("webhooks")
+            .unwrap_or_default();
+        rocket.manage(config).mount(
+            "/",
+            routes![
+                add_webhook_post,
+                delete_webhook,
+                ping,
+                redeliver,
+                webhooks_get,
+                webhooks_get_no_auth,
+            ],
+        )
+    })
+}